@@ -3,43 +3,87 @@
 #![feature(generic_arg_infer)]
 #![feature(type_alias_impl_trait)]
 #![feature(impl_trait_in_assoc_type)]
+#![feature(let_chains)]
 #![deny(
     clippy::mem_forget,
     reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
     holding buffers for the duration of a data transfer."
 )]
 
-use core::ops::DerefMut;
-use defmt::{error, info};
+use core::future::Future;
+use core::pin::pin;
+use defmt::{error, info, warn};
 use embassy_executor::Spawner;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_time::{Duration, Timer};
-use embedded_graphics::mono_font::ascii::{FONT_5X8, FONT_6X10, FONT_6X12, FONT_6X9};
-use embedded_graphics::mono_font::MonoTextStyle;
-use embedded_graphics::pixelcolor::Gray8;
-use embedded_graphics::prelude::*;
-use embedded_graphics::primitives::{PrimitiveStyleBuilder, Rectangle};
-use embedded_graphics::text::{LineHeight, Text};
-use embedded_text::alignment::HorizontalAlignment;
-use embedded_text::style::{HeightMode, TextBoxStyleBuilder};
-use embedded_text::TextBox;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use esp_hal::clock::CpuClock;
-use esp_hal::gpio::{AnyPin, Level, Output, OutputConfig, Pin};
+use esp_hal::gpio::{Level, Output, OutputConfig};
+#[cfg(feature = "parl-io")]
+use esp_hal::gpio::{Input, InputConfig, Pull};
 use esp_hal::interrupt::Priority;
 use esp_hal::interrupt::software::SoftwareInterruptControl;
-use esp_hal::peripherals::{DMA_CH0, PARL_IO};
+use esp_hal::peripherals::{ADC1, DMA_CH0};
+#[cfg(feature = "lcd-cam")]
+use esp_hal::peripherals::LCD_CAM;
+#[cfg(feature = "parl-io")]
+use esp_hal::peripherals::PARL_IO;
+#[cfg(any(feature = "spi", feature = "hc595"))]
+use esp_hal::spi::AnySpi;
 use esp_hal::rng::Rng;
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::timer::timg::TimerGroup;
+#[cfg(feature = "esp32c6")]
+use esp_hal::tsens::TemperatureSensor;
+use esp_hal::usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagRx};
+use esp_hal::Async;
 use esp_hal_embassy::InterruptExecutor;
 use esp_println as _;
-use matrix_controller_esp32::matrix_parl_io::{DmaFrameBuffer, MatrixParlIo, MatrixParlIoPins};
+use matrix_controller_esp32::board;
+use matrix_controller_esp32::board::{LdrPin, MatrixInterface, StatusLed};
+use matrix_controller_esp32::board_pins;
+use matrix_controller_esp32::brightness;
+use matrix_controller_esp32::brightness::LdrSensor;
+use matrix_controller_esp32::buttons::{Buttons, DIAGNOSTIC_BUTTON};
+use matrix_controller_esp32::config::{
+    flash_config_store, load_calibration, DITHERING_STORE_ID, GAMMA_STORE_ID,
+    ORIENTATION_STORE_ID, POWER_STORE_ID, TIMING_STORE_ID,
+};
+#[cfg(feature = "parl-io")]
+use matrix_controller_esp32::diagnostics;
+use matrix_controller_esp32::console;
+use matrix_controller_esp32::display;
+use matrix_controller_esp32::display::{Calibration, DisplayStatus};
+use matrix_controller_esp32::driver::MatrixDriver;
+#[cfg(feature = "lcd-cam")]
+use matrix_controller_esp32::matrix_lcd_cam::{MatrixLcdCam, MatrixLcdCamBuffers};
+#[cfg(feature = "parl-io")]
+use matrix_controller_esp32::matrix_parl_io::{MatrixParlIo, MatrixParlIoBuffers};
+#[cfg(feature = "spi")]
+use matrix_controller_esp32::matrix_spi::MatrixSpi;
+#[cfg(feature = "hc595")]
+use matrix_controller_esp32::matrix_hc595::MatrixHc595;
+use matrix_controller_esp32::network;
+use matrix_controller_esp32::watchdog;
+use matrix_core::arrivals::{Arrival, ArrivalStatus, ArrivalsBoard};
+use matrix_core::calibration;
+use matrix_core::compositor::Compositor;
+use matrix_core::dimming::{Brightness, DimmingConfig};
+use matrix_core::dither::Dithering;
+use matrix_core::double_buffer::DoubleBuffer;
+use matrix_core::framebuffer::{DmaFrameBuffer, Orientation};
+use matrix_core::gamma::GammaLut;
+use matrix_core::power::{PowerBudget, PowerConfig};
+use matrix_core::test_pattern::TestPattern;
+use matrix_core::timing::DisplayTiming;
+use matrix_core::watchdog::Subsystem;
 use static_cell::make_static;
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     error!("{}", info);
+    // so that whatever was latched last doesn't stay lit
+    let _blank = board::blank_on_panic();
     loop {}
 }
 
@@ -49,8 +93,6 @@ extern crate alloc;
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
-type SharedFrameBuf = Mutex<CriticalSectionRawMutex, DmaFrameBuffer>;
-
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
@@ -63,14 +105,48 @@ async fn main(spawner: Spawner) {
 
     info!("Embassy initialized!");
 
-    let timg0 = TimerGroup::new(peripherals.TIMG0);
-    let rng = Rng::new(peripherals.RNG);
-
-    // don't need wifi for bad apple demo - uncomment this line to init the network stack
-    // net_init(&spawner, timg0, &mut rng.clone(), peripherals.RADIO_CLK, peripherals.WIFI).await;
+    watchdog::init();
+    spawner
+        .spawn(watchdog::supervise(TimerGroup::new(peripherals.TIMG1).wdt))
+        .unwrap();
 
-    let fbuf = DmaFrameBuffer::new();
-    let shared_fb: &SharedFrameBuf = make_static!(Mutex::new(fbuf));
+    let mut fbuf: DmaFrameBuffer = DmaFrameBuffer::new();
+    match flash_config_store().get(ORIENTATION_STORE_ID).map(|o| o.parse::<Orientation>()) {
+        Ok(Ok(orientation)) => fbuf.set_orientation(orientation),
+        _ => info!("No orientation configured, using the default"),
+    }
+    match flash_config_store().get(TIMING_STORE_ID).map(|t| t.parse::<DisplayTiming>()) {
+        Ok(Ok(timing)) if display::supports_timing(&timing) => fbuf.set_timing(timing),
+        _ => info!("No display timing configured, using the default"),
+    }
+    match flash_config_store().get(DITHERING_STORE_ID).map(|d| d.parse::<Dithering>()) {
+        Ok(Ok(dithering)) => fbuf.set_dithering(dithering),
+        _ => info!("No dithering configured, using the default"),
+    }
+    match flash_config_store().get(GAMMA_STORE_ID).map(|g| g.parse::<GammaLut>()) {
+        Ok(Ok(gamma)) => fbuf.set_gamma(gamma),
+        _ => info!("No gamma configured, using the default"),
+    }
+    match flash_config_store().get(POWER_STORE_ID).map(|p| p.parse::<PowerConfig>()) {
+        Ok(Ok(power)) => display::set_power_config(power),
+        _ => info!("No power limits configured, using the default"),
+    }
+    let front_fb: &'static mut DmaFrameBuffer = make_static!(fbuf);
+    let shared_fb: &DoubleBuffer = make_static!(DoubleBuffer::new(make_static!(fbuf)));
+    let brightness: &Brightness = make_static!(Brightness::new(u8::MAX));
+    let layers: &Layers = make_static!(Mutex::new(Compositor::new()));
+    {
+        let mut layers = layers.lock().await;
+        layers.layer_mut(VIDEO_LAYER).set_z(1);
+        layers.layer_mut(PATTERN_LAYER).set_z(i8::MAX);
+        layers.layer_mut(PATTERN_LAYER).set_visible(false);
+        layers.layer_mut(TEST_PATTERN_LAYER).set_z(i8::MAX);
+        layers.layer_mut(TEST_PATTERN_LAYER).set_visible(false);
+    }
+    if let Some(gains) = load_calibration() {
+        info!("Loaded the uniformity calibration");
+        display::update_calibration(|c| c.gains = gains);
+    }
 
     let sw_ints = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
     let software_interrupt = sw_ints.software_interrupt1;
@@ -78,54 +154,259 @@ async fn main(spawner: Spawner) {
     let hp_executor: &mut InterruptExecutor<1> = make_static!(InterruptExecutor::new(software_interrupt));
     let high_pri_spawner = hp_executor.start(Priority::Priority3);
 
+    let pins = board_pins!(peripherals);
+
+    #[cfg(feature = "parl-io")]
     high_pri_spawner
         .spawn(matrix(
-            MatrixParlIoPins {
-                sck: peripherals.GPIO19.degrade(),
-                sdo: peripherals.GPIO18.degrade(),
-                le_mod: peripherals.GPIO17.degrade(),
-                row0: peripherals.GPIO2.degrade(),
-                row1: peripherals.GPIO23.degrade(),
-                row2: peripherals.GPIO22.degrade(),
-                row3: peripherals.GPIO21.degrade(),
-            },
+            pins.matrix,
+            // pulled down so that if it isn't wired, every LED shows up as faulty rather than as
+            // fine
+            pins.sdo_return
+                .map(|pin| Input::new(pin, InputConfig::default().with_pull(Pull::Down))),
             peripherals.PARL_IO,
             peripherals.DMA_CH0,
+            front_fb,
             shared_fb,
+            brightness,
+        ))
+        .unwrap();
+    #[cfg(feature = "lcd-cam")]
+    high_pri_spawner
+        .spawn(matrix(
+            pins.matrix,
+            peripherals.LCD_CAM,
+            peripherals.DMA_CH0,
+            front_fb,
+            shared_fb,
+            brightness,
+        ))
+        .unwrap();
+    #[cfg(any(feature = "spi", feature = "hc595"))]
+    high_pri_spawner
+        .spawn(matrix(
+            pins.matrix,
+            peripherals.SPI2.into(),
+            peripherals.DMA_CH0,
+            front_fb,
+            shared_fb,
+            brightness,
         ))
         .unwrap();
     info!("spawned matrix");
 
-    spawner.spawn(bad_apple(shared_fb)).unwrap();
+    spawner
+        .spawn(auto_dim(
+            LdrSensor::new(peripherals.ADC1, pins.ldr, false),
+            brightness,
+        ))
+        .unwrap();
 
-    // spawner.spawn(blink(peripherals.GPIO15.degrade())).unwrap();
+    spawner.spawn(bad_apple(shared_fb, layers)).unwrap();
+    spawner.spawn(apply_calibration(shared_fb, layers)).unwrap();
+    spawner.spawn(test_patterns(shared_fb, layers)).unwrap();
+    spawner.spawn(diagnostic_button(Buttons::new(pins.buttons))).unwrap();
+    let (console_rx, _) = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async().split();
+    spawner.spawn(serial_console(console_rx)).unwrap();
 
-    let style = MonoTextStyle::new(&FONT_6X10, Gray8::WHITE);
-    let textbox_style = TextBoxStyleBuilder::new().alignment(HorizontalAlignment::Center).paragraph_spacing(0).line_height(LineHeight::Percent(90)).build();
-    let text_bounds = Rectangle::new(Point::new(0, -1), Size::new(96, 20));
-    let textbox = TextBox::with_textbox_style("To S.Waterfront\n1 min & 15 min", text_bounds, style, textbox_style);
-    // let rect_style = PrimitiveStyleBuilder::new().fill_color(Gray8::new(255)).stroke_width(0).build();
-    // let t = Text::new("To S.Waterfront", Point::new(1, 14), style);
-    // let style2 = MonoTextStyle::new(&FONT_5X8, Gray8::new(50));
-    // let t2 = Text::new("1 min & 15 min", Point::new(1, 7), style2);
+    #[cfg(feature = "esp32c6")]
+    match TemperatureSensor::new(peripherals.TSENS, Default::default()) {
+        Ok(sensor) => spawner.spawn(chip_temperature(sensor)).unwrap(),
+        Err(e) => error!("failed to start the temperature sensor: {:?}", e),
+    }
 
-    {
-        let mut fb = shared_fb.lock().await;
-        textbox.draw(fb.deref_mut()).unwrap();
-        // t.draw(fb.deref_mut()).unwrap();
-        // t2.draw(fb.deref_mut()).unwrap();
-        // Rectangle::new(Point::new(4, 7), Size::new(8, 1)).into_styled(rect_style).draw(fb.deref_mut()).unwrap();
+    if let Some(led) = pins.status_led {
+        spawner.spawn(status_led(led)).unwrap();
+    }
+
+    // live predictions aren't fetched yet, so this is the sign's usual stop
+    let mut board = ArrivalsBoard::new();
+    board.set_arrivals([
+        Arrival::new("", "S.Waterfront", &[1, 15], ArrivalStatus::OnTime).unwrap(),
+    ]);
+    spawner.spawn(arrivals(board, shared_fb, layers)).unwrap();
+
+    // last, since this waits for the network to come up
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let mut rng = Rng::new(peripherals.RNG);
+    network::net_init(&spawner, timg0, &mut rng, peripherals.RADIO_CLK, peripherals.WIFI).await;
+}
+
+/// The content layers, which are composited into the back buffer whenever one of them changes
+type Layers = Mutex<CriticalSectionRawMutex, Compositor<4>>;
+/// The arrivals board
+const ARRIVALS_LAYER: usize = 0;
+/// Bad Apple, which covers the arrivals board once it starts
+const VIDEO_LAYER: usize = 1;
+/// The uniformity calibration pattern, on top of the content while it's shown
+const PATTERN_LAYER: usize = 2;
+/// The test pattern in diagnostic mode, on top of everything while it's shown
+const TEST_PATTERN_LAYER: usize = 3;
+
+/// How often a content source checks in while it's waiting on the render loop
+const CHECK_IN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Run `wait`, which waits on the render loop, with `source` checking in meanwhile.
+///
+/// Waiting for the render loop to pick a frame up can legitimately take a while (e.g. while the
+/// display is blanked or being tested for faults), and a render loop that has stalled is caught as
+/// such. The same goes for the locks on the layers and the back buffer, which are held by other
+/// sources while they wait.
+async fn waiting_on_render<T>(source: Subsystem, wait: impl Future<Output = T>) -> T {
+    let mut wait = pin!(wait);
+    loop {
+        match with_timeout(CHECK_IN_INTERVAL, wait.as_mut()).await {
+            Ok(output) => return output,
+            Err(_) => watchdog::check_in(source),
+        }
+    }
+}
+
+/// Flatten the content layers into the back buffer and present it, for `source`
+async fn present_layers(layers: &Layers, fb: &DoubleBuffer, source: Subsystem) {
+    waiting_on_render(source, async {
+        layers.lock().await.flatten(&mut fb.back().await);
+        fb.present().await;
+    })
+    .await;
+}
+
+/// Keep the arrivals board scrolling and paging, presenting a frame only when it's changed
+#[embassy_executor::task]
+async fn arrivals(mut board: ArrivalsBoard, fb: &'static DoubleBuffer, layers: &'static Layers) {
+    loop {
+        watchdog::check_in(Subsystem::Arrivals);
+        let changed = {
+            let mut layers = waiting_on_render(Subsystem::Arrivals, layers.lock()).await;
+            let layer = layers.layer_mut(ARRIVALS_LAYER);
+            board.draw(layer, Instant::now().as_millis() as u32).unwrap()
+        };
+        if changed {
+            present_layers(layers, fb, Subsystem::Arrivals).await;
+        }
+        Timer::after(Duration::from_secs(1) / FPS).await;
+    }
+}
+
+/// Redraw everything whenever the uniformity calibration changes, and show or hide the calibration
+/// pattern
+#[embassy_executor::task]
+async fn apply_calibration(fb: &'static DoubleBuffer, layers: &'static Layers) {
+    loop {
+        // idle until there's a new calibration
+        watchdog::pause(Subsystem::Calibration);
+        display::CALIBRATION_CHANGED.wait().await;
+        watchdog::check_in(Subsystem::Calibration);
+        let Calibration { gains, pattern } = display::calibration();
+        waiting_on_render(Subsystem::Calibration, async {
+            // held until both buffers have the gains, so nothing else presents in between
+            let mut layers = layers.lock().await;
+            let layer = layers.layer_mut(PATTERN_LAYER);
+            if let Some(level) = pattern {
+                calibration::draw_pattern(layer, level).unwrap();
+            }
+            layer.set_visible(pattern.is_some());
+            // the gains only apply to pixels as they're drawn
+            fb.back().await.set_calibration(gains);
+            layers.invalidate();
+            layers.flatten(&mut fb.back().await);
+            fb.present().await;
+            // the other buffer is the back one now, and the next flatten redraws all of it again
+            fb.back().await.set_calibration(gains);
+        })
+        .await;
+    }
+}
+
+/// Draw the test pattern whenever it changes, and step it along if it's animated
+#[embassy_executor::task]
+async fn test_patterns(fb: &'static DoubleBuffer, layers: &'static Layers) {
+    let mut step = 0;
+    loop {
+        watchdog::check_in(Subsystem::TestPatterns);
+        let pattern = display::test_pattern();
+        waiting_on_render(Subsystem::TestPatterns, async {
+            let mut layers = layers.lock().await;
+            let layer = layers.layer_mut(TEST_PATTERN_LAYER);
+            if let Some(pattern) = pattern {
+                // the walks light LEDs by where they are in the chain, so they need its mapping
+                pattern.draw(layer, &*fb.back().await, step).unwrap();
+            }
+            layer.set_visible(pattern.is_some());
+        })
+        .await;
+        present_layers(layers, fb, Subsystem::TestPatterns).await;
+        let changed = display::TEST_PATTERN_CHANGED.wait();
+        match pattern.and_then(TestPattern::step_ms) {
+            Some(ms) => match with_timeout(Duration::from_millis(ms), changed).await {
+                Ok(()) => step = 0,
+                Err(_) => step += 1,
+            },
+            None => {
+                // idle until the pattern changes
+                watchdog::pause(Subsystem::TestPatterns);
+                changed.await;
+                step = 0;
+            }
+        }
+    }
+}
+
+/// Holding the diagnostic button for this long leaves diagnostic mode
+const LONG_PRESS: Duration = Duration::from_secs(2);
+
+/// Step through the test patterns with the diagnostic button, from the first one to the last and
+/// then back to the content, or straight back to the content with a long press
+#[embassy_executor::task]
+async fn diagnostic_button(mut buttons: Buttons<'static>) {
+    loop {
+        let held = buttons.press(DIAGNOSTIC_BUTTON).await;
+        let pattern = match display::test_pattern() {
+            _ if held >= LONG_PRESS => None,
+            Some(pattern) => pattern.next(),
+            None => Some(TestPattern::ALL[0]),
+        };
+        match pattern {
+            Some(pattern) => info!("Test pattern: {}", pattern.name()),
+            None => info!("Test pattern off"),
+        }
+        display::set_test_pattern(pattern);
     }
 }
 
 #[embassy_executor::task]
-async fn blink(pin: AnyPin<'static>) {
-    let mut led = Output::new(pin, Level::Low, OutputConfig::default());
+async fn serial_console(rx: UsbSerialJtagRx<'static, Async>) {
+    console::run(rx).await
+}
 
+/// Show the display status on the status LED: on while it's running, blinking while it's starting
+/// or recovering, and off while it's blanked
+#[embassy_executor::task]
+async fn status_led(StatusLed { pin, active_low }: StatusLed<'static>) {
+    let mut led = Output::new(pin, Level::from(active_low), OutputConfig::default());
+    let mut blink = false;
+    loop {
+        let lit = match display::status() {
+            DisplayStatus::Running => true,
+            DisplayStatus::Starting | DisplayStatus::Recovering => {
+                blink = !blink;
+                blink
+            }
+            DisplayStatus::Blanked => false,
+        };
+        led.set_level(Level::from(lit != active_low));
+        Timer::after_millis(250).await;
+    }
+}
+
+/// Measure the chip temperature for the power budget every second
+#[cfg(feature = "esp32c6")]
+#[embassy_executor::task]
+async fn chip_temperature(sensor: TemperatureSensor<'static>) {
     loop {
-        info!("blink");
-        led.toggle();
-        Timer::after_millis(2000).await;
+        let celsius = sensor.get_temperature().to_celsius();
+        display::POWER.record_temperature(celsius as i32);
+        Timer::after_secs(1).await;
     }
 }
 
@@ -137,7 +418,7 @@ const FPS: u32 = 30;
 static BAD_APPLE: &[u8; FRAME_W * FRAME_H * FRAME_COUNT] = include_bytes!("../../bad_apple.rgb");
 
 #[embassy_executor::task]
-async fn bad_apple(fb: &'static SharedFrameBuf) {
+async fn bad_apple(fb: &'static DoubleBuffer, layers: &'static Layers) {
     let frames = unsafe {
         &*(BAD_APPLE.as_ptr() as *const [[[u8; FRAME_W]; FRAME_H]; FRAME_COUNT])
     };
@@ -147,36 +428,305 @@ async fn bad_apple(fb: &'static SharedFrameBuf) {
     loop {
         for i in 0..FRAME_COUNT {
             {
-                let mut fb = fb.lock().await;
-                for y in 0..FRAME_H {
-                    for x in 0..FRAME_W {
-                        fb.set_pixel_internal(x, y, Gray8::new(frames[i][y][x]));
-                    }
-                }
+                watchdog::check_in(Subsystem::Video);
+                let mut layers = waiting_on_render(Subsystem::Video, layers.lock()).await;
+                layers.layer_mut(VIDEO_LAYER).blit_gray8(frames[i].as_flattened());
             }
+            present_layers(layers, fb, Subsystem::Video).await;
             // technically a bit slow but whatever
             Timer::after(Duration::from_secs(1) / FPS).await;
         }
     }
 }
 
+#[embassy_executor::task]
+async fn auto_dim(
+    sensor: LdrSensor<'static, ADC1<'static>, LdrPin>,
+    brightness: &'static Brightness,
+) {
+    brightness::run(sensor, DimmingConfig::default(), brightness).await
+}
+
+/// Consecutive failed renders before the peripheral is reset
+const RENDER_RETRIES: u32 = 3;
+/// Resets without a successful render before giving up and blanking the display
+const MAX_RESETS: u32 = 3;
+/// How long the display is held blanked before trying again
+const BLANKED_TIME: Duration = Duration::from_secs(10);
+
+/// Render from `front` until `RENDER_RETRIES` renders in a row fail, swapping it with the back
+/// buffer after every successful one, then give the driver back so that it can be reset. The
+/// brightness is limited to keep within the power budget.
+async fn render_loop<D: MatrixDriver>(
+    mut m: D,
+    front: &mut &'static mut DmaFrameBuffer,
+    fb: &DoubleBuffer,
+    brightness: &Brightness,
+    timing: &mut DisplayTiming,
+    resets: &mut u32,
+) -> D {
+    // while frames keep coming, swap often enough for 30 fps video. Otherwise send as many
+    // refreshes per transfer as the driver can, so that it's restarted as rarely as possible.
+    let mut refreshes = D::max_refreshes();
+    let mut failures = 0;
+    let mut budget = PowerBudget::new(display::power_config());
+    budget.set_frame(&**front);
+    while failures < RENDER_RETRIES {
+        watchdog::check_in(Subsystem::Render);
+        if display::POWER_CONFIG_CHANGED.try_take().is_some() {
+            budget = PowerBudget::new(display::power_config());
+            budget.set_frame(&**front);
+        }
+        if let Some(new_timing) = display::TIMING.try_take() {
+            let applied = if !D::supports_timing(&new_timing) {
+                warn!("a clock rate of {} kHz isn't supported", new_timing.clock_khz);
+                false
+            } else if let Err(e) = m.set_timing(&new_timing) {
+                error!("failed to change the clock rate: {:?}", e);
+                false
+            } else {
+                true
+            };
+            if applied {
+                *timing = new_timing;
+                // the on-time changes how much current the frame draws
+                front.set_timing(*timing);
+                budget.set_frame(&**front);
+            }
+            display::TIMING_APPLIED.signal(applied);
+        }
+        front.set_timing(*timing);
+        let limited = budget.limit(brightness.get(), display::POWER.temperature());
+        front.set_brightness(limited);
+        display::POWER.record_estimate(&budget, limited);
+        front.advance_temporal_dither();
+        m = match m.render(&**front, refreshes).await {
+            Ok(m) => {
+                if failures > 0 || *resets > 0 {
+                    info!("display recovered");
+                }
+                failures = 0;
+                *resets = 0;
+                display::set_status(DisplayStatus::Running);
+                if fb.swap(front) {
+                    budget.set_frame(&**front);
+                    refreshes = D::max_refreshes().min(2);
+                } else {
+                    refreshes = D::max_refreshes();
+                }
+                m
+            }
+            Err((e, m)) => {
+                // keep showing the same frame, since it's the one that failed
+                failures += 1;
+                warn!("failed to render ({}/{}): {:?}", failures, RENDER_RETRIES, e);
+                display::set_status(DisplayStatus::Recovering);
+                m
+            }
+        };
+    }
+    m
+}
+
+/// Go back to the default timing after the driver couldn't be set up with `timing`, so that a
+/// saved timing that doesn't work can't keep the display from starting. The default always should,
+/// so this panics if it was the default that failed.
+fn reset_timing(timing: &mut DisplayTiming, e: impl defmt::Format) {
+    if *timing == DisplayTiming::default() {
+        defmt::panic!("failed to set up the matrix peripheral: {:?}", e);
+    }
+    error!("failed to set up the matrix peripheral, going back to the default timing: {:?}", e);
+    *timing = DisplayTiming::default();
+}
+
+/// Hold the display blanked for `BLANKED_TIME` after resetting didn't help
+async fn give_up(blank_for: impl Future<Output = ()>) {
+    error!("display keeps failing, blanking it for {} s", BLANKED_TIME.as_secs());
+    display::set_status(DisplayStatus::Blanked);
+    watchdog::pause(Subsystem::Render);
+    blank_for.await;
+}
+
+#[cfg(feature = "parl-io")]
 #[embassy_executor::task]
 async fn matrix(
-    pins: MatrixParlIoPins<'static>,
-    parl_io: PARL_IO<'static>,
-    dma: DMA_CH0<'static>,
-    fb: &'static SharedFrameBuf,
+    mut pins: MatrixInterface<'static>,
+    sdo_return: Option<Input<'static>>,
+    mut parl_io: PARL_IO<'static>,
+    mut dma: DMA_CH0<'static>,
+    mut front: &'static mut DmaFrameBuffer,
+    fb: &'static DoubleBuffer,
+    brightness: &'static Brightness,
 ) {
-    let mut m = MatrixParlIo::new(parl_io, dma, pins);
-    // we actually don't care about data races, even if an update is half finished we want to
-    // render it because we'll get the rest of it soon
-    let fb_ptr = unsafe {
-        let mut fb = fb.lock().await;
-        let p1: *mut DmaFrameBuffer = fb.deref_mut();
-        &*p1
-    };
+    let mut buffers = MatrixParlIoBuffers::new();
+    let mut timing = front.timing();
+    let mut detected_faults = false;
+    let mut resets = 0;
     loop {
-        m = m.render(fb_ptr).await.expect("failed to render");
-        Timer::after_micros(10).await;
+        let mut m = match MatrixParlIo::new(
+            parl_io.reborrow(),
+            dma.reborrow(),
+            pins.reborrow(),
+            buffers,
+            &timing,
+            &display::STATS,
+        ) {
+            Ok(m) => m,
+            Err((e, returned)) => {
+                buffers = returned;
+                reset_timing(&mut timing, e);
+                continue;
+            }
+        };
+        if !detected_faults && let Some(sdo_return) = &sdo_return {
+            detected_faults = true;
+            // this takes a few seconds
+            watchdog::pause(Subsystem::Render);
+            m = match m.detect_faults(front, sdo_return).await {
+                Ok((m, faults)) => {
+                    diagnostics::set_faults(faults);
+                    m
+                }
+                Err((e, m)) => {
+                    error!("failed to run error detection: {:?}", e);
+                    m
+                }
+            };
+        }
+
+        m = render_loop(m, &mut front, fb, brightness, &mut timing, &mut resets).await;
+
+        buffers = m.release();
+        resets += 1;
+        if resets <= MAX_RESETS {
+            warn!("resetting the matrix peripheral ({}/{})", resets, MAX_RESETS);
+        } else {
+            give_up(pins.blank_for(BLANKED_TIME)).await;
+            resets = 0;
+        }
+    }
+}
+
+#[cfg(feature = "lcd-cam")]
+#[embassy_executor::task]
+async fn matrix(
+    mut pins: MatrixInterface<'static>,
+    mut lcd_cam: LCD_CAM<'static>,
+    mut dma: DMA_CH0<'static>,
+    mut front: &'static mut DmaFrameBuffer,
+    fb: &'static DoubleBuffer,
+    brightness: &'static Brightness,
+) {
+    let mut buffers = MatrixLcdCamBuffers::new();
+    let mut timing = front.timing();
+    let mut resets = 0;
+    loop {
+        let m = match MatrixLcdCam::new(
+            lcd_cam.reborrow(),
+            dma.reborrow(),
+            pins.reborrow(),
+            buffers,
+            &timing,
+            &display::STATS,
+        ) {
+            Ok(m) => m,
+            Err((e, returned)) => {
+                buffers = returned;
+                reset_timing(&mut timing, e);
+                continue;
+            }
+        };
+        let m = render_loop(m, &mut front, fb, brightness, &mut timing, &mut resets).await;
+
+        buffers = m.release();
+        resets += 1;
+        if resets <= MAX_RESETS {
+            warn!("resetting the matrix peripheral ({}/{})", resets, MAX_RESETS);
+        } else {
+            give_up(pins.blank_for(BLANKED_TIME)).await;
+            resets = 0;
+        }
+    }
+}
+
+#[cfg(feature = "spi")]
+#[embassy_executor::task]
+async fn matrix(
+    mut pins: MatrixInterface<'static>,
+    mut spi: AnySpi<'static>,
+    mut dma: DMA_CH0<'static>,
+    mut front: &'static mut DmaFrameBuffer,
+    fb: &'static DoubleBuffer,
+    brightness: &'static Brightness,
+) {
+    let mut timing = front.timing();
+    let mut resets = 0;
+    loop {
+        let m = match MatrixSpi::new(
+            spi.reborrow(),
+            dma.reborrow(),
+            pins.reborrow(),
+            &timing,
+            &display::STATS,
+        ) {
+            Ok(m) => m,
+            Err(e) => {
+                reset_timing(&mut timing, e);
+                continue;
+            }
+        };
+        let m = render_loop(m, &mut front, fb, brightness, &mut timing, &mut resets).await;
+
+        // dropping the driver shuts the peripheral down, and the next one sets it up from scratch
+        drop(m);
+        resets += 1;
+        if resets <= MAX_RESETS {
+            warn!("resetting the matrix peripheral ({}/{})", resets, MAX_RESETS);
+        } else {
+            give_up(pins.blank_for(BLANKED_TIME)).await;
+            resets = 0;
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "hc595")]
+#[embassy_executor::task]
+async fn matrix(
+    mut pins: MatrixInterface<'static>,
+    mut spi: AnySpi<'static>,
+    mut dma: DMA_CH0<'static>,
+    mut front: &'static mut DmaFrameBuffer,
+    fb: &'static DoubleBuffer,
+    brightness: &'static Brightness,
+) {
+    let mut timing = front.timing();
+    let mut resets = 0;
+    loop {
+        // this also blanks the display through the shift register
+        let m = match MatrixHc595::new(
+            spi.reborrow(),
+            dma.reborrow(),
+            pins.reborrow(),
+            &timing,
+            &display::STATS,
+        ) {
+            Ok(m) => m,
+            Err(e) => {
+                reset_timing(&mut timing, e);
+                continue;
+            }
+        };
+        let m = render_loop(m, &mut front, fb, brightness, &mut timing, &mut resets).await;
+
+        drop(m);
+        resets += 1;
+        if resets <= MAX_RESETS {
+            warn!("resetting the matrix peripheral ({}/{})", resets, MAX_RESETS);
+        } else {
+            // the display is only lit while the driver waits in between writes, so it's already
+            // blank
+            give_up(Timer::after(BLANKED_TIME)).await;
+            resets = 0;
+        }
+    }
+}
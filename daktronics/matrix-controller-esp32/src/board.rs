@@ -0,0 +1,205 @@
+/// Board profiles: which GPIOs the matrix, buttons, status LED, light sensor and RS-485
+/// transceiver are wired to. The profile is picked with a cargo feature, which also picks the
+/// chip:
+/// - `xiao-esp32c6`: the XIAO ESP32C6 prototype (the default)
+/// - `esp32s3-devkit`: an ESP32-S3-DevKitC-1
+/// - `hw05`: the hw05 controller PCB (`hw05-led-matrix-sign-controller`), an ESP32-C3 module
+///
+/// To wire a board differently, change its profile here; nothing else refers to GPIOs by number.
+/// The pin maps are macros rather than functions since they have to take individual pins out of
+/// `Peripherals`, leaving the rest of it for `main`.
+use esp_hal::gpio::AnyPin;
+
+pub use profile::*;
+
+/// Everything `board_pins!` takes out of `Peripherals`
+pub struct BoardPins<'a> {
+    pub matrix: MatrixInterface<'a>,
+    /// SDO of the last panel, for drivers that support error detection
+    pub sdo_return: Option<AnyPin<'a>>,
+    pub buttons: ButtonPins<'a>,
+    pub status_led: Option<StatusLed<'a>>,
+    /// The LDR board
+    pub ldr: LdrPin,
+    pub rs485: Option<Rs485Pins<'a>>,
+}
+
+pub enum ButtonPins<'a> {
+    /// A single button, which pulls the pin low when pressed
+    Single(AnyPin<'a>),
+    /// Four lines A-D with a button or DIP switch and a diode between each ordered pair of them.
+    /// A switch reads as closed when the line it's on is pulled low while the line its diode goes
+    /// to is driven low (see `sw-button.kicad_sch` in the hw05 design).
+    DiodeMatrix([AnyPin<'a>; 4]),
+}
+
+pub struct StatusLed<'a> {
+    pub pin: AnyPin<'a>,
+    pub active_low: bool,
+}
+
+/// A half-duplex RS-485 transceiver
+pub struct Rs485Pins<'a> {
+    pub tx: AnyPin<'a>,
+    pub rx: AnyPin<'a>,
+    /// Driver enable, high to transmit
+    pub de: AnyPin<'a>,
+}
+
+#[cfg(feature = "xiao-esp32c6")]
+mod profile {
+    use esp_hal::gpio::{Level, Output, OutputConfig};
+
+    pub type MatrixInterface<'a> = crate::driver::MatrixPins<'a>;
+    /// A0
+    pub type LdrPin = esp_hal::peripherals::GPIO0<'static>;
+
+    #[macro_export]
+    macro_rules! board_pins {
+        ($peripherals:ident) => {{
+            use ::esp_hal::gpio::Pin as _;
+            $crate::board::BoardPins {
+                matrix: $crate::driver::MatrixPins {
+                    sck: $peripherals.GPIO19.degrade(),
+                    sdo: $peripherals.GPIO18.degrade(),
+                    le_mod: $peripherals.GPIO17.degrade(),
+                    row0: $peripherals.GPIO2.degrade(),
+                    row1: $peripherals.GPIO23.degrade(),
+                    row2: $peripherals.GPIO22.degrade(),
+                    row3: $peripherals.GPIO21.degrade(),
+                    oe: Some($peripherals.GPIO20.degrade()),
+                },
+                sdo_return: Some($peripherals.GPIO1.degrade()),
+                // BOOT
+                buttons: $crate::board::ButtonPins::Single($peripherals.GPIO9.degrade()),
+                status_led: Some($crate::board::StatusLed {
+                    pin: $peripherals.GPIO15.degrade(),
+                    active_low: true,
+                }),
+                ldr: $peripherals.GPIO0,
+                rs485: None,
+            }
+        }};
+    }
+
+    /// Blank the display from the panic handler, by deselecting every row (RD A3). The display
+    /// stays blanked for as long as the returned pin is held.
+    pub fn blank_on_panic() -> impl Sized {
+        let row3 = unsafe { esp_hal::peripherals::GPIO21::steal() };
+        Output::new(row3, Level::High, OutputConfig::default())
+    }
+}
+
+#[cfg(feature = "esp32s3-devkit")]
+mod profile {
+    use esp_hal::gpio::{Level, Output, OutputConfig};
+
+    pub type MatrixInterface<'a> = crate::driver::MatrixPins<'a>;
+    /// ADC1 channel 0
+    pub type LdrPin = esp_hal::peripherals::GPIO1<'static>;
+
+    #[macro_export]
+    macro_rules! board_pins {
+        ($peripherals:ident) => {{
+            use ::esp_hal::gpio::Pin as _;
+            $crate::board::BoardPins {
+                matrix: $crate::driver::MatrixPins {
+                    sck: $peripherals.GPIO4.degrade(),
+                    sdo: $peripherals.GPIO5.degrade(),
+                    le_mod: $peripherals.GPIO6.degrade(),
+                    row0: $peripherals.GPIO7.degrade(),
+                    row1: $peripherals.GPIO15.degrade(),
+                    row2: $peripherals.GPIO16.degrade(),
+                    row3: $peripherals.GPIO17.degrade(),
+                    oe: Some($peripherals.GPIO18.degrade()),
+                },
+                sdo_return: Some($peripherals.GPIO8.degrade()),
+                // BOOT
+                buttons: $crate::board::ButtonPins::Single($peripherals.GPIO0.degrade()),
+                // the only LED on the board is an addressable RGB one
+                status_led: None,
+                ldr: $peripherals.GPIO1,
+                rs485: None,
+            }
+        }};
+    }
+
+    /// Blank the display from the panic handler, by deselecting every row (RD A3). The display
+    /// stays blanked for as long as the returned pin is held.
+    pub fn blank_on_panic() -> impl Sized {
+        let row3 = unsafe { esp_hal::peripherals::GPIO17::steal() };
+        Output::new(row3, Level::High, OutputConfig::default())
+    }
+}
+
+/// The hw05 drives the matrix through a 74HC595 (see `matrix_hc595.rs`), on an SPI bus it shares
+/// with an ENC28J60 Ethernet module, which isn't supported yet. Its DIP switches and buttons are
+/// in a diode matrix. It has no status LED.
+#[cfg(feature = "hw05")]
+mod profile {
+    use crate::matrix_hc595::BLANK;
+    use esp_hal::gpio::{Level, Output, OutputConfig, Pin};
+
+    pub type MatrixInterface<'a> = crate::matrix_hc595::Hc595Pins<'a>;
+    /// LDR on the Daktronics interface sheet
+    pub type LdrPin = esp_hal::peripherals::GPIO1<'static>;
+
+    #[macro_export]
+    macro_rules! board_pins {
+        ($peripherals:ident) => {{
+            use ::esp_hal::gpio::Pin as _;
+            $crate::board::BoardPins {
+                matrix: $crate::matrix_hc595::Hc595Pins {
+                    clk: $peripherals.GPIO6.degrade(),
+                    data: $peripherals.GPIO7.degrade(),
+                    latch: $peripherals.GPIO5.degrade(),
+                    // ENC28J60 CS
+                    deselect: Some($peripherals.GPIO4.degrade()),
+                },
+                sdo_return: None,
+                buttons: $crate::board::ButtonPins::DiodeMatrix([
+                    $peripherals.GPIO0.degrade(),
+                    $peripherals.GPIO3.degrade(),
+                    $peripherals.GPIO8.degrade(),
+                    $peripherals.GPIO10.degrade(),
+                ]),
+                status_led: None,
+                ldr: $peripherals.GPIO1,
+                // MAX3485, which always has its receiver enabled
+                rs485: Some($crate::board::Rs485Pins {
+                    tx: $peripherals.GPIO21.degrade(),
+                    rx: $peripherals.GPIO20.degrade(),
+                    de: $peripherals.GPIO9.degrade(),
+                }),
+            }
+        }};
+    }
+
+    /// Blank the display from the panic handler, by bit banging a control byte with RD A3 high
+    /// into the 74HC595. The display stays blanked for as long as the returned pins are held.
+    pub fn blank_on_panic() -> impl Sized {
+        let output = |pin, level| Output::new(pin, level, OutputConfig::default());
+        let mut clk = output(
+            unsafe { esp_hal::peripherals::GPIO6::steal() }.degrade(),
+            Level::High,
+        );
+        let mut data = output(
+            unsafe { esp_hal::peripherals::GPIO7::steal() }.degrade(),
+            Level::Low,
+        );
+        let mut latch = output(
+            unsafe { esp_hal::peripherals::GPIO5::steal() }.degrade(),
+            Level::Low,
+        );
+        // RD A3 high, and the clock gate closed, whatever the panels saw while this was shifted in
+        let control = BLANK;
+        for bit in (0..8).rev() {
+            clk.set_low();
+            data.set_level(Level::from(control & (1 << bit) != 0));
+            clk.set_high();
+        }
+        latch.set_high();
+        latch.set_low();
+        (clk, data, latch)
+    }
+}
@@ -0,0 +1,91 @@
+/// Ambient light based dimming, from the AF-6700's LDR board read through the ADC.
+///
+/// The AF-6700 also has a brightness control board that compares the LDR against two thresholds
+/// with an LM2901 (see led-matrices.md), but none of the board profiles wire up its outputs, and
+/// the LDR gives a continuous light level rather than three coarse ones anyway.
+use embassy_time::{Duration, Timer};
+use esp_hal::analog::adc::{Adc, AdcChannel, AdcConfig, AdcPin, Attenuation, RegisterAccess};
+use esp_hal::gpio::AnalogPin;
+use esp_hal::Blocking;
+use matrix_core::dimming::{Brightness, Dimmer, DimmingConfig, LightSensor};
+
+/// How often the light level is read
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// The LDR, read through the ADC.
+///
+/// Reads never wait for the ADC: a conversion is left running after every read, and the next one
+/// picks up its result, so the level is one read interval old.
+pub struct LdrSensor<'a, ADCI, PIN> {
+    adc: Adc<'a, ADCI, Blocking>,
+    pin: AdcPin<PIN, ADCI>,
+    /// Whether the reading goes down as the light level goes up, which depends on which side of
+    /// the divider the LDR is on
+    invert: bool,
+    /// The last light level read
+    level: u16,
+}
+
+impl<'a, ADCI, PIN> LdrSensor<'a, ADCI, PIN>
+where
+    ADCI: RegisterAccess + 'a,
+    PIN: AdcChannel + AnalogPin,
+{
+    pub fn new(adc: ADCI, pin: PIN, invert: bool) -> Self {
+        let mut config = AdcConfig::new();
+        let mut pin = config.enable_pin(pin, Attenuation::_11dB);
+        let mut adc = Adc::new(adc, config);
+        // this is only at startup, and a conversion only takes a few microseconds
+        let raw = loop {
+            if let Ok(raw) = adc.read_oneshot(&mut pin) {
+                break raw;
+            }
+        };
+        Self {
+            adc,
+            pin,
+            invert,
+            level: light_level(raw, invert),
+        }
+    }
+}
+
+/// Convert a raw ADC reading to a light level
+fn light_level(raw: u16, invert: bool) -> u16 {
+    // the ADC is 12 bits
+    let raw = raw.min(0xfff);
+    let level = (raw << 4) | (raw >> 8);
+    if invert {
+        u16::MAX - level
+    } else {
+        level
+    }
+}
+
+impl<ADCI, PIN> LightSensor for LdrSensor<'_, ADCI, PIN>
+where
+    ADCI: RegisterAccess,
+    PIN: AdcChannel,
+{
+    fn read(&mut self) -> u16 {
+        if let Ok(raw) = self.adc.read_oneshot(&mut self.pin) {
+            self.level = light_level(raw, self.invert);
+        }
+        // start the next conversion, which finishes long before the next read
+        let _ = self.adc.read_oneshot(&mut self.pin);
+        self.level
+    }
+}
+
+/// Continuously adjust `brightness` to the light level measured by `sensor`
+pub async fn run(
+    mut sensor: impl LightSensor,
+    config: DimmingConfig,
+    brightness: &Brightness,
+) -> ! {
+    let mut dimmer = Dimmer::new(config);
+    loop {
+        brightness.set(dimmer.update(sensor.read()));
+        Timer::after(INTERVAL).await;
+    }
+}
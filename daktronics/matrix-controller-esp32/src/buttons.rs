@@ -0,0 +1,76 @@
+/// The board's buttons (see `ButtonPins`). They're read by polling, since the diode matrix on the
+/// hw05 has to be scanned anyway, and polling slowly enough debounces them for free.
+use crate::board::ButtonPins;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::delay::Delay;
+use esp_hal::gpio::{DriveMode, Flex, Input, InputConfig, OutputConfig, Pull};
+
+/// How often a button is read while waiting for it
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The push buttons in the hw05's diode matrix, as the (line it's on, line its diode goes to)
+/// pairs of lines A-D. The rest of the pairs are the DIP switches.
+const DIODE_MATRIX_BUTTONS: [(usize, usize); 4] = [(0, 1), (0, 2), (0, 3), (1, 0)];
+
+/// The button that steps through the test patterns: BOOT on the dev boards, and the first push
+/// button on the hw05
+pub const DIAGNOSTIC_BUTTON: usize = 0;
+
+pub enum Buttons<'a> {
+    Single(Input<'a>),
+    /// Lines A-D, as open drain outputs that are released (and pulled up) unless they're being
+    /// driven low to scan them
+    DiodeMatrix([Flex<'a>; 4]),
+}
+
+impl<'a> Buttons<'a> {
+    pub fn new(pins: ButtonPins<'a>) -> Self {
+        match pins {
+            ButtonPins::Single(pin) => {
+                Self::Single(Input::new(pin, InputConfig::default().with_pull(Pull::Up)))
+            }
+            ButtonPins::DiodeMatrix(lines) => Self::DiodeMatrix(lines.map(|line| {
+                let mut line = Flex::new(line);
+                line.apply_output_config(
+                    &OutputConfig::default()
+                        .with_drive_mode(DriveMode::OpenDrain)
+                        .with_pull(Pull::Up),
+                );
+                line.set_high();
+                line.set_output_enable(true);
+                line.set_input_enable(true);
+                line
+            })),
+        }
+    }
+
+    /// Whether button `button` is held down. Boards with a single button only have button 0.
+    pub fn is_pressed(&mut self, button: usize) -> bool {
+        match self {
+            Self::Single(input) => button == 0 && input.is_low(),
+            Self::DiodeMatrix(lines) => {
+                let Some(&(read, drive)) = DIODE_MATRIX_BUTTONS.get(button) else {
+                    return false;
+                };
+                lines[drive].set_low();
+                // give the pull-up on the line being read time to lose against the diode
+                Delay::new().delay_micros(10);
+                let pressed = lines[read].is_low();
+                lines[drive].set_high();
+                pressed
+            }
+        }
+    }
+
+    /// Wait for button `button` to be pressed and released, returning how long it was held for
+    pub async fn press(&mut self, button: usize) -> Duration {
+        while !self.is_pressed(button) {
+            Timer::after(POLL_INTERVAL).await;
+        }
+        let pressed = Instant::now();
+        while self.is_pressed(button) {
+            Timer::after(POLL_INTERVAL).await;
+        }
+        pressed.elapsed()
+    }
+}
@@ -1,23 +1,18 @@
-use crate::config;
-use crate::config::{ConfigStore, PW_STORE_ID, SSID_STORE_ID};
+use crate::http::http_task;
 use crate::net_utils;
 use crate::net_utils::net_task;
 use core::convert::identity;
 use core::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
-use defmt::{error, info, warn};
+use defmt::{info, warn};
 use edge_dhcp::server::{Server, ServerOptions};
 use edge_nal::UdpBind;
 use edge_nal_embassy::{Udp, UdpBuffers};
 use embassy_executor::Spawner;
-use embassy_net::tcp::TcpSocket;
 use embassy_net::{Stack, StackResources, StaticConfigV4};
-use embassy_time::{Duration, Timer};
-use embedded_io_async::Write;
-use esp_storage::FlashStorageError;
+use embassy_time::Timer;
 use esp_wifi::wifi::{
     AccessPointConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState,
 };
-use percent_encoding::percent_decode_str;
 use smoltcp::wire::Ipv4Cidr;
 use static_cell::make_static;
 
@@ -54,133 +49,7 @@ pub async fn spawn_captive_portal(
 
     spawner.spawn(dhcp_task(ap_stack, GATEWAY_ADDR)).ok();
     spawner.spawn(captive_dns_task(ap_stack)).ok();
-    spawner.spawn(ap_task(ap_stack)).ok();
-}
-
-#[embassy_executor::task]
-async fn ap_task(net_stack: Stack<'static>) {
-    info!("Starting AP task");
-    let mut rx_buffer = [0; 1536];
-    let mut tx_buffer = [0; 1536];
-
-    let mut socket = TcpSocket::new(net_stack, &mut rx_buffer, &mut tx_buffer);
-    socket.set_timeout(Some(Duration::from_secs(10)));
-
-    loop {
-        info!("Waiting for connection...");
-
-        let r = socket
-            .accept(smoltcp::wire::IpListenEndpoint {
-                addr: None,
-                port: 80,
-            })
-            .await;
-
-        if let Err(e) = r {
-            warn!("Failed to connect: {:?}", e);
-            continue;
-        }
-        info!("Connected");
-
-        let mut buffer = [0u8; 1024];
-        let mut pos = 0;
-        let request: Option<&str> = loop {
-            match socket.read(&mut buffer).await {
-                Ok(0) => {
-                    info!("read EOF");
-                    break None;
-                }
-                Ok(len) => {
-                    let request = unsafe { core::str::from_utf8_unchecked(&buffer[..(pos + len)]) };
-                    if request.contains("\r\n\r\n") {
-                        info!("{}", request);
-                        break Some(request);
-                    } else {
-                        pos += len;
-                    }
-                }
-                Err(e) => {
-                    error!("read error: {:?}", e);
-                    break None;
-                }
-            }
-        };
-
-        if let Some(request) = request
-            && request.starts_with("POST / ")
-        {
-            let r = socket.write_all(b"HTTP/1.0 200 OK\r\n\r\n").await;
-            if let Err(e) = r {
-                warn!("Failed to write response: {:?}", e);
-            }
-            let r = socket.flush().await;
-            if let Err(e) = r {
-                warn!("Failed to flush socket: {:?}", e);
-            }
-
-            if let Some((ssid, pw)) = try {
-                let mut parts = (&request[request.find("\r\n\r\n")? + 4..])
-                    .split('&')
-                    .map(|v| {
-                        let mut s = v.split('=');
-                        Some((s.next()?, s.next()?))
-                    });
-                let mut fk = |k| parts.find_map(|v| if v?.0 == k { Some(v?.1) } else { None });
-                (fk("ssid")?, fk("pw")?)
-            } {
-                let decode = |s: &str| {
-                    let mut decoded = heapless::Vec::<u8, { config::CONFIG_ENTRY_LEN }>::new();
-                    percent_decode_str(s).collect_into(&mut decoded);
-                    heapless::String::<{ config::CONFIG_ENTRY_LEN }>::from_utf8(decoded).unwrap()
-                };
-                let ssid = decode(ssid);
-                let ssid = ssid.as_str();
-                let pw = decode(pw);
-                let pw = pw.as_str();
-                info!("SSID: {}, PW: {}", ssid, pw);
-                let mut c = ConfigStore::new();
-                let _ = c.set(SSID_STORE_ID, ssid).inspect_err(|e| match e {
-                    FlashStorageError::Other(i) => error!("flash storage error {}", i),
-                    _ => error!("other flash error"),
-                });
-                let _ = c.set(PW_STORE_ID, pw).inspect_err(|e| match e {
-                    FlashStorageError::Other(i) => error!("flash storage error {}", i),
-                    _ => error!("other flash error"),
-                });
-                info!("wrote to flash, resetting system to try to connect");
-                socket.close();
-                socket.abort();
-                esp_hal::system::software_reset();
-            } else {
-                info!("Failed to parse request");
-            }
-        } else {
-            if let Some(request) = request {
-                info!("handling request");
-                let r = socket
-                    .write_all(if request.starts_with("GET / ") {
-                        info!("sending index.html");
-                        include_bytes!("./web/index.html")
-                    } else {
-                        info!("sending 302");
-                        b"HTTP/1.1 302 Found\r\nLocation: http://192.168.2.1\r\n\r\n"
-                    })
-                    .await;
-                if let Err(e) = r {
-                    warn!("Failed to write response: {:?}", e);
-                }
-            }
-            let r = socket.flush().await;
-            if let Err(e) = r {
-                warn!("Failed to flush socket: {:?}", e);
-            }
-        }
-
-        // Timer::after_millis(1000).await;
-        socket.close();
-        // Timer::after_millis(1000).await;
-        socket.abort();
-    }
+    spawner.spawn(http_task(ap_stack, true)).ok();
 }
 
 #[embassy_executor::task]
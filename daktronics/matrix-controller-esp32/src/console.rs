@@ -0,0 +1,64 @@
+/// A serial console on the USB Serial/JTAG port, for typing commands into a serial monitor (e.g.
+/// `espflash monitor`). The replies go to the log, which is on the same port.
+///
+/// Commands are one per line:
+/// - `test <pattern>` shows a built-in test pattern (see `TestPattern`) over the content
+/// - `test off` goes back to the content
+use crate::display;
+use defmt::{info, warn};
+use embedded_io_async::Read;
+use esp_hal::usb_serial_jtag::UsbSerialJtagRx;
+use esp_hal::Async;
+use matrix_core::test_pattern::TestPattern;
+
+/// Longest command line
+const LINE_LEN: usize = 64;
+
+/// Read and run commands until the end of time
+pub async fn run(mut rx: UsbSerialJtagRx<'_, Async>) -> ! {
+    let mut line = heapless::Vec::<u8, LINE_LEN>::new();
+    let mut buffer = [0; 16];
+    loop {
+        let Ok(len) = rx.read(&mut buffer).await else {
+            continue;
+        };
+        for &byte in &buffer[..len] {
+            if byte == b'\r' || byte == b'\n' {
+                match core::str::from_utf8(&line) {
+                    Ok(command) => run_command(command.trim()),
+                    Err(_) => warn!("console: invalid UTF-8"),
+                }
+                line.clear();
+            } else if line.push(byte).is_err() {
+                warn!("console: line too long");
+                line.clear();
+            }
+        }
+    }
+}
+
+fn run_command(command: &str) {
+    if command.is_empty() {
+        return;
+    }
+    match command.split_once(' ') {
+        Some(("test", name)) => match display::parse_test_pattern(name.trim()) {
+            Ok(pattern) => {
+                display::set_test_pattern(pattern);
+                match pattern {
+                    Some(pattern) => info!("showing the {} test pattern", pattern.name()),
+                    None => info!("test pattern off"),
+                }
+            }
+            Err(_) => usage(),
+        },
+        _ => usage(),
+    }
+}
+
+fn usage() {
+    info!("usage: test <pattern>|off");
+    for pattern in TestPattern::ALL {
+        info!("  {}", pattern.name());
+    }
+}
@@ -0,0 +1,40 @@
+/// Results of the LED error detection (see `MatrixParlIo::detect_faults`), kept around so that
+/// they can be served over the network.
+use core::cell::RefCell;
+use core::fmt::Write;
+use defmt::{info, warn};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use matrix_core::error_detection::FaultMap;
+
+/// Longest fault report that gets served, which is enough for a few dozen faulty LEDs
+pub const REPORT_LEN: usize = 1024;
+
+static FAULTS: Mutex<CriticalSectionRawMutex, RefCell<Option<FaultMap>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Log the results of error detection and keep them for `fault_report`
+pub fn set_faults(faults: FaultMap) {
+    for panel in 0..faults.panels() {
+        let (open, short) = faults.panel_counts(panel);
+        if open + short > 0 {
+            warn!("panel {}: {} open LEDs, {} shorted LEDs", panel, open, short);
+        }
+    }
+    if faults.is_empty() {
+        info!("no faulty LEDs found");
+    }
+    FAULTS.lock(|f| f.replace(Some(faults)));
+}
+
+/// Text report of the last error detection results, or `None` if it hasn't been run. If there are
+/// too many faults to fit, the list of LEDs is cut short.
+pub fn fault_report() -> Option<heapless::String<REPORT_LEN>> {
+    FAULTS.lock(|f| {
+        let faults = f.borrow();
+        let mut report = heapless::String::new();
+        // running out of space just truncates the report
+        let _ = faults.as_ref()?.write_report(&mut report);
+        Some(report)
+    })
+}
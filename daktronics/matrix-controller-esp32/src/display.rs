@@ -0,0 +1,139 @@
+/// State shared between the matrix task and the rest of the firmware
+use crate::driver::{Driver, MatrixDriver};
+use core::cell::{Cell, RefCell};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+use matrix_core::calibration::GainMap;
+use matrix_core::framebuffer::DmaFrameBuffer;
+use matrix_core::power::{PowerConfig, PowerStats};
+use matrix_core::test_pattern::{ParseTestPatternError, TestPattern};
+use matrix_core::timing::{DisplayTiming, RefreshStats};
+
+/// Counters kept by the matrix driver
+pub static STATS: RefreshStats = RefreshStats::new();
+
+/// Estimated current and power kept by the matrix task, and the chip temperature
+pub static POWER: PowerStats = PowerStats::new();
+
+static POWER_CONFIG: Mutex<CriticalSectionRawMutex, Cell<PowerConfig>> =
+    Mutex::new(Cell::new(PowerConfig::new()));
+
+/// Limits on the current draw and chip temperature
+pub fn power_config() -> PowerConfig {
+    POWER_CONFIG.lock(Cell::get)
+}
+
+/// Change the power limits and signal `POWER_CONFIG_CHANGED`
+pub fn set_power_config(config: PowerConfig) {
+    POWER_CONFIG.lock(|c| c.set(config));
+    POWER_CONFIG_CHANGED.signal(());
+}
+
+/// Signalled whenever the power limits change, for the matrix task to pick them up
+pub static POWER_CONFIG_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether the framebuffer and the driver can both work with `timing`. Anything else is rejected
+/// before it's applied or saved.
+pub fn supports_timing(timing: &DisplayTiming) -> bool {
+    <DmaFrameBuffer>::supports_timing(timing) && Driver::supports_timing(timing)
+}
+
+/// Signal a new display timing to the matrix task, which applies it before the next refresh
+pub static TIMING: Signal<CriticalSectionRawMutex, DisplayTiming> = Signal::new();
+
+/// Signalled by the matrix task with whether it could apply the last `TIMING`
+pub static TIMING_APPLIED: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Have the matrix task apply `timing`, and wait for whether it could. It's dropped if the matrix
+/// task doesn't get to it within a second (e.g. while the display is blanked or being tested for
+/// faults), so it's only ever applied if this returns true, and only then should it be saved.
+pub async fn apply_timing(timing: DisplayTiming) -> bool {
+    TIMING_APPLIED.reset();
+    TIMING.signal(timing);
+    match with_timeout(Duration::from_secs(1), TIMING_APPLIED.wait()).await {
+        Ok(applied) => applied,
+        Err(_) => {
+            TIMING.reset();
+            false
+        }
+    }
+}
+
+/// The uniformity calibration, and whether the calibration pattern is being shown
+#[derive(Clone, Copy)]
+pub struct Calibration {
+    pub gains: GainMap,
+    /// Level to show the calibration pattern at, if it's being shown
+    pub pattern: Option<u8>,
+}
+
+static CALIBRATION: Mutex<CriticalSectionRawMutex, RefCell<Calibration>> =
+    Mutex::new(RefCell::new(Calibration {
+        gains: GainMap::new(),
+        pattern: None,
+    }));
+
+/// Signalled whenever the calibration changes, for the content side to redraw with it
+pub static CALIBRATION_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn calibration() -> Calibration {
+    CALIBRATION.lock(|c| *c.borrow())
+}
+
+/// Change the calibration and signal `CALIBRATION_CHANGED`
+pub fn update_calibration(f: impl FnOnce(&mut Calibration)) {
+    CALIBRATION.lock(|c| f(&mut c.borrow_mut()));
+    CALIBRATION_CHANGED.signal(());
+}
+
+static TEST_PATTERN: Mutex<CriticalSectionRawMutex, Cell<Option<TestPattern>>> =
+    Mutex::new(Cell::new(None));
+
+/// Signalled whenever the test pattern changes, for the content side to draw it
+pub static TEST_PATTERN_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The built-in test pattern being shown over the content, if the display is in diagnostic mode
+pub fn test_pattern() -> Option<TestPattern> {
+    TEST_PATTERN.lock(Cell::get)
+}
+
+/// Show a test pattern over the content, or go back to the content with `None`, and signal
+/// `TEST_PATTERN_CHANGED`
+pub fn set_test_pattern(pattern: Option<TestPattern>) {
+    TEST_PATTERN.lock(|p| p.set(pattern));
+    TEST_PATTERN_CHANGED.signal(());
+}
+
+/// Parse a test pattern name as it's typed into the console or posted to `/test`, where `off`
+/// leaves diagnostic mode
+pub fn parse_test_pattern(s: &str) -> Result<Option<TestPattern>, ParseTestPatternError> {
+    match s {
+        "off" => Ok(None),
+        name => name.parse().map(Some),
+    }
+}
+
+/// What the matrix task is up to
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum DisplayStatus {
+    Starting,
+    /// Refreshing normally
+    Running,
+    /// Refreshes are failing, and it's retrying them and resetting the peripheral
+    Recovering,
+    /// Resetting didn't help, so the display is being held blanked for a while before trying again
+    Blanked,
+}
+
+static STATUS: Mutex<CriticalSectionRawMutex, Cell<DisplayStatus>> =
+    Mutex::new(Cell::new(DisplayStatus::Starting));
+
+pub fn status() -> DisplayStatus {
+    STATUS.lock(Cell::get)
+}
+
+pub fn set_status(status: DisplayStatus) {
+    STATUS.lock(|s| s.set(status));
+}
@@ -0,0 +1,85 @@
+/// What the render loop needs from a matrix driver, so that it doesn't care which peripheral is
+/// clocking the data out. The backend is picked with a cargo feature: `parl-io` (the default on the
+/// C6), `lcd-cam` (for the S3) or `spi`, which works on anything.
+use embassy_time::{Duration, Timer};
+use esp_hal::gpio::{AnyPin, Level, Output, OutputConfig};
+use matrix_core::framebuffer::DmaFrameBuffer;
+use matrix_core::timing::DisplayTiming;
+
+/// The pins the matrix is wired to. Which GPIOs these are depends on the board (see `board.rs`).
+pub struct MatrixPins<'a> {
+    pub sck: AnyPin<'a>,
+    pub sdo: AnyPin<'a>,
+    pub le_mod: AnyPin<'a>,
+    pub row0: AnyPin<'a>,
+    pub row1: AnyPin<'a>,
+    pub row2: AnyPin<'a>,
+    /// RD A3, which blanks the display when high
+    pub row3: AnyPin<'a>,
+    /// CD OE/SW/ED. The display works without it, since it's blanked through the row decoder, but
+    /// it's needed for error detection.
+    pub oe: Option<AnyPin<'a>>,
+}
+
+impl MatrixPins<'_> {
+    pub fn reborrow(&mut self) -> MatrixPins<'_> {
+        MatrixPins {
+            sck: self.sck.reborrow(),
+            sdo: self.sdo.reborrow(),
+            le_mod: self.le_mod.reborrow(),
+            row0: self.row0.reborrow(),
+            row1: self.row1.reborrow(),
+            row2: self.row2.reborrow(),
+            row3: self.row3.reborrow(),
+            oe: self.oe.as_mut().map(AnyPin::reborrow),
+        }
+    }
+
+    /// Hold the display blanked for `duration` by driving the pins directly, for when the
+    /// peripheral can't be trusted to do it. This deselects every row (RD A3 high) and, if it's
+    /// wired, disables the column drivers too.
+    pub async fn blank_for(&mut self, duration: Duration) {
+        let _row3 = Output::new(self.row3.reborrow(), Level::High, OutputConfig::default());
+        let _oe = self
+            .oe
+            .as_mut()
+            .map(|oe| Output::new(oe.reborrow(), Level::High, OutputConfig::default()));
+        Timer::after(duration).await;
+    }
+}
+
+// the drivers only run on embassy's single threaded executors, so there's no need for `Send`
+#[allow(async_fn_in_trait)]
+pub trait MatrixDriver<const PANELS: usize = 2>: Sized {
+    type Error: defmt::Format;
+
+    /// Most refreshes that `render` can send in one go. The render loop only checks in with the
+    /// watchdog, swaps frames and changes the timing in between, so this has to stay small.
+    fn max_refreshes() -> usize;
+
+    /// Show `fb` for `refreshes` refreshes. The driver is handed back either way, so that it can
+    /// carry on after an error.
+    async fn render(
+        self,
+        fb: &DmaFrameBuffer<PANELS>,
+        refreshes: usize,
+    ) -> Result<Self, (Self::Error, Self)>;
+
+    /// Whether the peripheral can clock data out at the rate in `timing`. Check this before saving
+    /// a timing, along with `DmaFrameBuffer::supports_timing` for the rest of it.
+    fn supports_timing(timing: &DisplayTiming) -> bool;
+
+    /// Apply the clock rate from `timing`, leaving the old one in place if that fails. The rest of
+    /// the timing is up to the framebuffer.
+    fn set_timing(&mut self, timing: &DisplayTiming) -> Result<(), Self::Error>;
+}
+
+/// The driver picked with the cargo features
+#[cfg(feature = "parl-io")]
+pub type Driver = crate::matrix_parl_io::MatrixParlIo<'static>;
+#[cfg(feature = "lcd-cam")]
+pub type Driver = crate::matrix_lcd_cam::MatrixLcdCam<'static>;
+#[cfg(feature = "spi")]
+pub type Driver = crate::matrix_spi::MatrixSpi<'static>;
+#[cfg(feature = "hc595")]
+pub type Driver = crate::matrix_hc595::MatrixHc595<'static>;
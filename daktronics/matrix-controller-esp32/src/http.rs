@@ -0,0 +1,332 @@
+/// The web interface, served on the captive portal in AP mode and on the local network once the
+/// sign is connected: the setup form, the diagnostics, and the settings that can be changed live.
+use crate::config;
+use crate::config::{
+    ConfigError, FlashConfigStore, DITHERING_STORE_ID, GAMMA_STORE_ID, ORIENTATION_STORE_ID,
+    POWER_STORE_ID, PW_STORE_ID, SSID_STORE_ID, TIMING_STORE_ID,
+};
+use crate::diagnostics;
+use crate::display;
+use crate::watchdog;
+use core::fmt::Write as _;
+use defmt::{error, info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_time::Duration;
+use embedded_io_async::Write;
+use esp_storage::FlashStorageError;
+use matrix_core::dither::Dithering;
+use matrix_core::form::{decode_field, form_field};
+use matrix_core::framebuffer::Orientation;
+use matrix_core::gamma::GammaLut;
+use matrix_core::power::PowerConfig;
+use matrix_core::test_pattern::TestPattern;
+use matrix_core::timing::DisplayTiming;
+
+type Message = heapless::String<{ diagnostics::REPORT_LEN }>;
+
+/// Serve HTTP on port 80. With `captive`, every unknown page redirects to the setup form, so that
+/// phones show it when they join the access point.
+#[embassy_executor::task]
+pub(crate) async fn http_task(net_stack: Stack<'static>, captive: bool) {
+    info!("Starting HTTP task");
+    let mut rx_buffer = [0; 1536];
+    let mut tx_buffer = [0; 1536];
+
+    let mut socket = TcpSocket::new(net_stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(10)));
+
+    loop {
+        info!("Waiting for connection...");
+
+        let r = socket
+            .accept(smoltcp::wire::IpListenEndpoint {
+                addr: None,
+                port: 80,
+            })
+            .await;
+
+        if let Err(e) = r {
+            warn!("Failed to connect: {:?}", e);
+            continue;
+        }
+        info!("Connected");
+
+        let mut buffer = [0u8; 1024];
+        let mut pos = 0;
+        let request: Option<&str> = loop {
+            match socket.read(&mut buffer[pos..]).await {
+                Ok(0) => {
+                    info!("read EOF");
+                    break None;
+                }
+                Ok(len) => {
+                    pos += len;
+                    let request = unsafe { core::str::from_utf8_unchecked(&buffer[..pos]) };
+                    if request.contains("\r\n\r\n") {
+                        info!("{}", request);
+                        break Some(request);
+                    } else if pos == buffer.len() {
+                        warn!("request too long");
+                        break None;
+                    }
+                }
+                Err(e) => {
+                    error!("read error: {:?}", e);
+                    break None;
+                }
+            }
+        };
+
+        let mut restart = false;
+        if let Some(request) = request {
+            info!("handling request");
+            let body = if request.starts_with("POST / ") {
+                let mut message = Message::new();
+                restart = save_settings(request, &mut message).await;
+                Some(message)
+            } else if request.starts_with("GET /faults ") {
+                info!("sending fault report");
+                diagnostics::fault_report()
+            } else if request.starts_with("GET /stats ") {
+                info!("sending refresh statistics");
+                let mut stats = Message::new();
+                let _ = writeln!(stats, "status: {:?}", display::status());
+                let _ = write!(stats, "{}", display::STATS);
+                let _ = write!(stats, "{}", display::POWER);
+                if let Some(subsystem) = watchdog::last_stall() {
+                    let _ = writeln!(stats, "last reset: {} stalled", subsystem);
+                }
+                Some(stats)
+            } else if request.starts_with("POST /timing ") {
+                Some(set_timing(request).await)
+            } else if request.starts_with("POST /calibration ") {
+                Some(set_calibration(request))
+            } else if request.starts_with("POST /test ") {
+                Some(set_test_pattern(request))
+            } else {
+                None
+            };
+            let r = socket
+                .write_all(if body.is_some() {
+                    // the body goes out as a second write below
+                    b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n"
+                } else if request.starts_with("GET / ") {
+                    info!("sending index.html");
+                    include_bytes!("./web/index.html")
+                } else if captive {
+                    info!("sending 302");
+                    b"HTTP/1.1 302 Found\r\nLocation: http://192.168.2.1\r\n\r\n"
+                } else {
+                    info!("sending 404");
+                    b"HTTP/1.0 404 Not Found\r\n\r\n"
+                })
+                .await;
+            if let Err(e) = r {
+                warn!("Failed to write response: {:?}", e);
+            }
+            if let Some(body) = body {
+                let r = socket.write_all(body.as_bytes()).await;
+                if let Err(e) = r {
+                    warn!("Failed to write response: {:?}", e);
+                }
+            }
+        }
+        let r = socket.flush().await;
+        if let Err(e) = r {
+            warn!("Failed to flush socket: {:?}", e);
+        }
+
+        socket.close();
+        socket.abort();
+        if restart {
+            info!("resetting system to apply the new settings");
+            esp_hal::system::software_reset();
+        }
+    }
+}
+
+fn log_config_error(e: &ConfigError<FlashStorageError>) {
+    match e {
+        ConfigError::Storage(FlashStorageError::Other(i)) => error!("flash storage error {}", i),
+        ConfigError::Storage(_) => error!("other flash error"),
+        ConfigError::TooLong => error!("config value too long"),
+        ConfigError::Invalid => error!("invalid config value"),
+    }
+}
+
+/// Save `value` as config entry `id`, returning whether it's changed
+fn save(c: &mut FlashConfigStore, id: u32, value: &str) -> bool {
+    if c.get(id).is_ok_and(|saved| saved == value) {
+        return false;
+    }
+    c.set(id, value).inspect_err(log_config_error).is_ok()
+}
+
+/// Save the settings from a `POST /` form, writing what was saved to `message`. Any of the fields
+/// can be left out. The display timing and power limits are applied straight away, but the rest
+/// are only read at boot, so this returns whether the sign has to restart for them: when there
+/// are Wi-Fi credentials to connect with, or the orientation, dithering or gamma has changed.
+async fn save_settings(request: &str, message: &mut Message) -> bool {
+    let mut c = config::flash_config_store();
+    let mut restart = false;
+    if let Some(ssid) = form_field(request, "ssid")
+        && let Some(pw) = form_field(request, "pw")
+        && let Some(ssid) = decode_field::<{ config::CONFIG_ENTRY_LEN }>(ssid)
+        && let Some(pw) = decode_field::<{ config::CONFIG_ENTRY_LEN }>(pw)
+        && !ssid.is_empty()
+    {
+        info!("SSID: {}, PW: {}", ssid.as_str(), pw.as_str());
+        let _ = c.set(SSID_STORE_ID, ssid.as_str()).inspect_err(log_config_error);
+        let _ = c.set(PW_STORE_ID, pw.as_str()).inspect_err(log_config_error);
+        let _ = writeln!(message, "connecting to {}", ssid);
+        restart = true;
+    }
+    if let Some(orientation) = form_field(request, "orientation")
+        && orientation.parse::<Orientation>().is_ok()
+        && save(&mut c, ORIENTATION_STORE_ID, orientation)
+    {
+        info!("Orientation: {}", orientation);
+        let _ = writeln!(message, "orientation set to {}", orientation);
+        restart = true;
+    }
+    if let Some(dithering) = form_field(request, "dithering")
+        && let Some(dithering) = decode_field::<{ config::CONFIG_ENTRY_LEN }>(dithering)
+        && dithering.parse::<Dithering>().is_ok()
+        && save(&mut c, DITHERING_STORE_ID, &dithering)
+    {
+        info!("Dithering: {}", dithering.as_str());
+        let _ = writeln!(message, "dithering set to {}", dithering);
+        restart = true;
+    }
+    if let Some(gamma) = form_field(request, "gamma")
+        && let Some(gamma) = decode_field::<{ config::CONFIG_ENTRY_LEN }>(gamma)
+        && gamma.parse::<GammaLut>().is_ok()
+        && save(&mut c, GAMMA_STORE_ID, &gamma)
+    {
+        info!("Gamma: {}", gamma.as_str());
+        let _ = writeln!(message, "gamma set to {}", gamma);
+        restart = true;
+    }
+    if form_field(request, "timing").is_some_and(|timing| !timing.is_empty()) {
+        let _ = message.push_str(&set_timing(request).await);
+    }
+    if let Some(power) = form_field(request, "power")
+        && let Some(power) = decode_field::<{ config::CONFIG_ENTRY_LEN }>(power)
+        && let Ok(config) = power.parse::<PowerConfig>()
+    {
+        info!("Power limits: {}", power.as_str());
+        display::set_power_config(config);
+        save(&mut c, POWER_STORE_ID, &power);
+        let _ = writeln!(message, "power limits set to {}", power);
+    }
+    if restart {
+        let _ = writeln!(message, "restarting to apply the settings");
+    }
+    restart
+}
+
+/// Parse the display timing from a form, rejecting timings that the display can't be refreshed with
+fn parse_timing(request: &str) -> Option<heapless::String<{ config::CONFIG_ENTRY_LEN }>> {
+    let timing = decode_field(form_field(request, "timing")?)?;
+    let parsed = timing.parse::<DisplayTiming>().ok()?;
+    display::supports_timing(&parsed).then_some(timing)
+}
+
+/// Apply the display timing from a `POST /timing` form straight away, and save it once the
+/// display has taken it, returning a message for the response body
+async fn set_timing(request: &str) -> Message {
+    let mut message = Message::new();
+    match parse_timing(request) {
+        // checked by `parse_timing`
+        Some(timing) if display::apply_timing(timing.parse().unwrap()).await => {
+            info!("Display timing: {}", timing.as_str());
+            let _ = config::flash_config_store()
+                .set(TIMING_STORE_ID, timing.as_str())
+                .inspect_err(log_config_error);
+            let _ = writeln!(message, "display timing set to {}", timing);
+        }
+        Some(timing) => {
+            let _ = writeln!(message, "the display couldn't switch to {}, try again", timing);
+        }
+        None => {
+            let _ = writeln!(
+                message,
+                "expected timing=clock_khz,blanking,latch_width,on_time, with a clock rate the \
+                 driver supports and the blanking shorter than a row"
+            );
+        }
+    }
+    message
+}
+
+/// Apply a `POST /calibration` form straight away, returning a message for the response body. It
+/// can change the gains (see `GainMap::apply_form`), which are saved, and show the calibration
+/// pattern with `pattern=level` or hide it with `pattern=off`.
+fn set_calibration(request: &str) -> Message {
+    let mut message = Message::new();
+    let mut calibration = display::calibration();
+    let pattern = match form_field(request, "pattern") {
+        None => Ok(calibration.pattern),
+        Some("off") => Ok(None),
+        Some(level) => level.parse().map(Some),
+    };
+    let (Ok(pattern), Ok(changed)) = (pattern, calibration.gains.apply_form(request)) else {
+        let _ = writeln!(
+            message,
+            "expected area=x,y,width,height&gain=g, row=y&gains=hex or pattern=level|off"
+        );
+        return message;
+    };
+    calibration.pattern = pattern;
+    display::update_calibration(|c| *c = calibration);
+    if changed {
+        info!("Uniformity calibration changed");
+        match config::save_calibration(&calibration.gains) {
+            Ok(()) => {
+                let _ = writeln!(message, "calibration saved");
+            }
+            Err(e) => {
+                log_config_error(&ConfigError::Storage(e));
+                let _ = writeln!(message, "failed to save the calibration");
+            }
+        }
+    }
+    match pattern {
+        Some(level) => {
+            let _ = writeln!(message, "showing the calibration pattern at {}", level);
+        }
+        None => {
+            let _ = writeln!(message, "calibration pattern off");
+        }
+    }
+    message
+}
+
+/// Show or hide a built-in test pattern from a `POST /test` form (`pattern=name` or `pattern=off`),
+/// returning a message for the response body
+fn set_test_pattern(request: &str) -> Message {
+    let mut message = Message::new();
+    match form_field(request, "pattern").map(display::parse_test_pattern) {
+        Some(Ok(pattern)) => {
+            display::set_test_pattern(pattern);
+            match pattern {
+                Some(pattern) => {
+                    info!("Test pattern: {}", pattern.name());
+                    let _ = writeln!(message, "showing the {} test pattern", pattern);
+                }
+                None => {
+                    let _ = writeln!(message, "test pattern off");
+                }
+            }
+        }
+        _ => {
+            let _ = write!(message, "expected pattern=off or one of:");
+            for pattern in TestPattern::ALL {
+                let _ = write!(message, " {}", pattern);
+            }
+            let _ = writeln!(message);
+        }
+    }
+    message
+}
@@ -5,12 +5,60 @@
 #![feature(let_chains)]
 #![feature(try_blocks)]
 #![feature(impl_trait_in_assoc_type)]
-#![feature(iter_collect_into)]
 extern crate alloc;
 
-mod matrix_spi;
+const _: () = assert!(
+    cfg!(feature = "xiao-esp32c6") as u8
+        + cfg!(feature = "esp32s3-devkit") as u8
+        + cfg!(feature = "hw05") as u8
+        == 1,
+    "enable exactly one board feature: xiao-esp32c6, esp32s3-devkit or hw05"
+);
+const _: () = assert!(
+    cfg!(feature = "esp32c3") as u8
+        + cfg!(feature = "esp32c6") as u8
+        + cfg!(feature = "esp32s3") as u8
+        == 1,
+    "boards with different chips can't be combined"
+);
+const _: () = assert!(
+    cfg!(feature = "parl-io") as u8
+        + cfg!(feature = "lcd-cam") as u8
+        + cfg!(feature = "spi") as u8
+        + cfg!(feature = "hc595") as u8
+        == 1,
+    "enable exactly one matrix driver feature: parl-io, lcd-cam, spi or hc595"
+);
+#[cfg(all(feature = "parl-io", not(feature = "esp32c6")))]
+compile_error!("the parl-io driver needs a chip with PARL_IO, like the esp32c6");
+#[cfg(all(feature = "lcd-cam", not(feature = "esp32s3")))]
+compile_error!("the lcd-cam driver needs a chip with LCD_CAM, like the esp32s3");
+#[cfg(any(
+    all(feature = "hc595", not(feature = "hw05")),
+    all(feature = "hw05", not(feature = "hc595"))
+))]
+compile_error!("the hw05 board only works with the hc595 driver, and vice versa");
+
+pub mod board;
+pub mod driver;
+#[cfg(feature = "spi")]
+pub mod matrix_spi;
+#[cfg(feature = "parl-io")]
 pub mod matrix_parl_io;
+#[cfg(feature = "lcd-cam")]
+pub mod matrix_lcd_cam;
+#[cfg(feature = "hc595")]
+pub mod matrix_hc595;
+#[cfg(any(feature = "parl-io", feature = "lcd-cam"))]
+mod refresh_chain;
+pub mod brightness;
+pub mod buttons;
 pub mod config;
+pub mod console;
+pub mod diagnostics;
+pub mod display;
 pub mod network;
+pub mod watchdog;
 mod net_utils;
-mod captive;
\ No newline at end of file
+mod captive;
+mod http;
\ No newline at end of file
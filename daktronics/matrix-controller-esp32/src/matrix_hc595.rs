@@ -0,0 +1,222 @@
+/// 74HC595 driver, for the hw05 board, which only has three wires to the matrix: SPI clock and
+/// data, plus the latch of a 74HC595 that's also clocked from SPI. The latched outputs of the
+/// shift register drive RD A0-A3 (QA-QD) and CD LE/MOD (QF), and QE gates the SPI clock through
+/// to CD CLK. CD SI comes from the serial output of the shift register (QH').
+///
+/// So everything is sent over SPI, in two kinds of writes:
+/// - a control byte on its own, then a latch pulse, which sets the row, LE and the clock gate.
+///   The clock gate is closed while shifting it, so the panels don't see it.
+/// - a scan row of pixel data followed by a control byte, with the clock gate open. The panels see
+///   the pixel data 8 bits late (QH' is the bit that was shifted in 8 clocks earlier), which is
+///   exactly what the trailing control byte pushes through, and then the latch pulse closes the
+///   gate and latches the row in the column drivers at the same time.
+///
+/// Like the SPI driver, each row is lit for the on-time of its bit plane with a timer.
+use core::ops::RangeInclusive;
+use crate::driver::MatrixDriver;
+use embassy_time::{Instant, Timer};
+use esp_hal::dma::{DmaChannelFor, DmaRxBuf, DmaTxBuf};
+use esp_hal::dma_buffers;
+use esp_hal::gpio::{AnyPin, Level, Output, OutputConfig};
+use esp_hal::spi::master::{Config, ConfigError, Spi, SpiDmaBus};
+use esp_hal::spi::{AnySpi, Mode};
+use esp_hal::time::Rate;
+use matrix_core::framebuffer::{DmaFrameBuffer, BITS, MAX_PANELS, PANEL_CHAIN, ROWS};
+use matrix_core::timing::{DisplayTiming, RefreshStats};
+
+/// Bytes of pixel data in a scan row of the longest chain
+const MAX_ROW_LEN: usize = MAX_PANELS * PANEL_CHAIN / 8;
+
+/// Clock rates that the 80 MHz SPI clock can be divided down to (by at most 1024), up to what a
+/// signal routed through the GPIO matrix can keep up with
+const CLOCK_KHZ: RangeInclusive<u32> = 79..=40_000;
+
+/// RD A3 (QD), which blanks the display when high
+pub(crate) const BLANK: u8 = 1 << 3;
+/// Opens the clock gate (QE), so that the panels are clocked along with the shift register
+const CLOCK: u8 = 1 << 4;
+/// CD LE/MOD (QF)
+const LATCH: u8 = 1 << 5;
+
+/// The pins the hw05 matrix interface is wired to
+pub struct Hc595Pins<'a> {
+    pub clk: AnyPin<'a>,
+    pub data: AnyPin<'a>,
+    /// RCLK of the 74HC595
+    pub latch: AnyPin<'a>,
+    /// Chip select of another device on the same SPI bus, which is held high to keep it off the
+    /// bus
+    pub deselect: Option<AnyPin<'a>>,
+}
+
+impl Hc595Pins<'_> {
+    pub fn reborrow(&mut self) -> Hc595Pins<'_> {
+        Hc595Pins {
+            clk: self.clk.reborrow(),
+            data: self.data.reborrow(),
+            latch: self.latch.reborrow(),
+            deselect: self.deselect.as_mut().map(AnyPin::reborrow),
+        }
+    }
+}
+
+#[derive(Debug, defmt::Format)]
+pub enum RenderError {
+    Spi(esp_hal::spi::Error),
+    Config(ConfigError),
+}
+
+pub struct MatrixHc595<'a> {
+    spi: SpiDmaBus<'a, esp_hal::Async>,
+    config: Config,
+    latch: Output<'a>,
+    _deselect: Option<Output<'a>>,
+    clock_khz: u32,
+    stats: &'a RefreshStats,
+    /// When the last `render` finished, for measuring the refresh rate
+    last_render: Instant,
+}
+
+impl<'a> MatrixHc595<'a> {
+    pub fn new(
+        spi: AnySpi<'a>,
+        dma_channel: impl DmaChannelFor<AnySpi<'a>>,
+        Hc595Pins {
+            clk,
+            data,
+            latch,
+            deselect,
+        }: Hc595Pins<'a>,
+        timing: &DisplayTiming,
+        stats: &'a RefreshStats,
+    ) -> Result<Self, RenderError> {
+        // see `matrix_spi.rs` for why there's an rx buffer
+        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) =
+            dma_buffers!(1, MAX_ROW_LEN + 1);
+        let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer).unwrap();
+        let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer).unwrap();
+
+        // the shift register and the column drivers both sample on the rising edge. opening the
+        // clock gate while the clock idles high gives the panels one extra clock, but that's
+        // before the pixel data, so it falls off the end of the chain.
+        let config = Config::default()
+            .with_frequency(Rate::from_khz(timing.clock_khz))
+            .with_mode(Mode::_3);
+        let spi = Spi::new(spi, config)
+            .map_err(RenderError::Config)?
+            .with_sck(clk)
+            .with_mosi(data)
+            .with_dma(dma_channel)
+            .with_buffers(dma_rx_buf, dma_tx_buf)
+            .into_async();
+
+        let mut matrix = Self {
+            spi,
+            config,
+            latch: Output::new(latch, Level::Low, OutputConfig::default()),
+            _deselect: deselect.map(|pin| Output::new(pin, Level::High, OutputConfig::default())),
+            clock_khz: timing.clock_khz,
+            stats,
+            last_render: Instant::now(),
+        };
+        // the shift register outputs are random at power on
+        matrix
+            .spi
+            .write(&[BLANK | CLOCK])
+            .map_err(RenderError::Spi)?;
+        matrix.pulse_latch();
+        Ok(matrix)
+    }
+
+    fn pulse_latch(&mut self) {
+        self.latch.set_high();
+        self.latch.set_low();
+    }
+
+    /// Shift `data` out and latch the last byte of it into the shift register outputs
+    async fn write_latched(&mut self, data: &[u8]) -> Result<(), RenderError> {
+        self.spi.write_async(data).await.map_err(RenderError::Spi)?;
+        self.pulse_latch();
+        Ok(())
+    }
+
+    /// Shift in scan row `row` while the display is blanked, then light it for `on_time_us` µs.
+    /// `data` is the pixel data followed by a spare byte for the control byte.
+    async fn render_row(
+        &mut self,
+        row: usize,
+        data: &mut [u8],
+        on_time_us: u64,
+    ) -> Result<(), RenderError> {
+        let row = row as u8;
+        let (control, _) = data.split_last_mut().unwrap();
+        *control = row | BLANK | LATCH;
+        self.write_latched(data).await?;
+        if on_time_us > 0 {
+            self.write_latched(&[row]).await?;
+            Timer::after_micros(on_time_us).await;
+        }
+        // ready for the next row's data
+        self.write_latched(&[row | BLANK | CLOCK]).await
+    }
+
+    async fn refresh<const PANELS: usize>(
+        &mut self,
+        fb: &DmaFrameBuffer<PANELS>,
+    ) -> Result<(), RenderError> {
+        let mut data = [0; MAX_ROW_LEN + 1];
+        let data = &mut data[..PANELS * PANEL_CHAIN / 8 + 1];
+        for plane in 0..BITS as usize {
+            let on_time_us = fb.plane_lit_cycles(plane) as u64 * 1000 / self.clock_khz as u64;
+            for row in 0..ROWS {
+                let (_, pixels) = data.split_last_mut().unwrap();
+                pixels.copy_from_slice(fb.row_data(plane, row));
+                self.render_row(row, data, on_time_us).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const PANELS: usize> MatrixDriver<PANELS> for MatrixHc595<'_> {
+    type Error = RenderError;
+
+    /// Every row is its own transfer anyway, so there's nothing to gain from sending more
+    fn max_refreshes() -> usize {
+        1
+    }
+
+    async fn render(
+        mut self,
+        fb: &DmaFrameBuffer<PANELS>,
+        refreshes: usize,
+    ) -> Result<Self, (RenderError, Self)> {
+        let start = Instant::now();
+        for _ in 0..refreshes {
+            if let Err(e) = self.refresh(fb).await {
+                self.stats.record_error();
+                return Err((e, self));
+            }
+        }
+        let now = Instant::now();
+        self.stats.record_refreshes(
+            refreshes,
+            (now - start).as_micros(),
+            (now - self.last_render).as_micros(),
+        );
+        self.last_render = now;
+        Ok(self)
+    }
+
+    fn supports_timing(timing: &DisplayTiming) -> bool {
+        CLOCK_KHZ.contains(&timing.clock_khz)
+    }
+
+    fn set_timing(&mut self, timing: &DisplayTiming) -> Result<(), RenderError> {
+        let config = self.config.with_frequency(Rate::from_khz(timing.clock_khz));
+        self.spi.apply_config(&config).map_err(RenderError::Config)?;
+        self.config = config;
+        self.clock_khz = timing.clock_khz;
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+/// LCD_CAM driver, for the ESP32-S3, which has no PARL_IO. The LCD half of LCD_CAM in i8080 mode
+/// is just as good at clocking out a byte per cycle: the 8 data lines carry the same `Entry` bits
+/// as with PARL_IO, and the write strobe (WR) is the clock.
+///
+/// The LCD peripheral sends until the DMA runs out of data, so unlike with PARL_IO the descriptor
+/// chain doesn't loop: a looping chain would never finish, leaving no point at which to swap in the
+/// next frame. Instead every transfer is a chain of up to `MAX_REFRESHES` refreshes back to back
+/// (see `refresh_chain.rs`), with an EOF at the end, and the render loop starts the next one. Every
+/// refresh ends with the display blanked, and the data lines hold the last byte in between
+/// transfers, so the display stays dark while the next transfer is being started. The framebuffer
+/// being shown is encoded before every transfer, which only rewrites what changed since the last
+/// one.
+///
+/// Like with PARL_IO, the gap between transfers is a known limitation: it dims every pixel evenly
+/// and flickers at the restart rate, both of which show up in `RefreshStats`.
+///
+/// Error detection isn't supported with this driver yet.
+use core::ops::RangeInclusive;
+use crate::driver::{MatrixDriver, MatrixPins};
+use crate::refresh_chain::{RefreshChain, REFRESH_DESCRIPTORS};
+use embassy_time::Instant;
+use esp_hal::dma::{DmaChannelFor, DmaDescriptor};
+use esp_hal::lcd_cam::lcd::i8080::{Command, Config, ConfigError, I8080};
+use esp_hal::lcd_cam::LcdCam;
+use esp_hal::peripherals::LCD_CAM;
+use esp_hal::time::Rate;
+use esp_hal::Async;
+use matrix_core::encoding::DmaEncoding;
+use matrix_core::framebuffer::DmaFrameBuffer;
+use matrix_core::timing::{DisplayTiming, RefreshStats};
+use static_cell::make_static;
+
+/// Most refreshes sent per transfer, which is as many as the descriptor chain has room for
+const MAX_REFRESHES: usize = 2;
+
+/// Clock rates that twice over can be divided from the 40 MHz crystal (by at most 256), up to what
+/// a signal routed through the GPIO matrix can keep up with
+const CLOCK_KHZ: RangeInclusive<u32> = 79..=40_000;
+
+#[derive(Debug, defmt::Format)]
+pub enum RenderError {
+    Dma(esp_hal::dma::DmaError),
+    Config(ConfigError),
+}
+
+#[derive(Debug)]
+pub struct MatrixLcdCam<'a, const PANELS: usize = 2> {
+    i8080: I8080<'a, Async>,
+    refresh_chain: RefreshChain,
+    /// The framebuffer being shown, encoded for the refresh chain to point at
+    encoding: &'static mut DmaEncoding<PANELS>,
+    config: Config,
+    stats: &'a RefreshStats,
+    /// When the last `render` finished, for measuring the refresh rate
+    last_render: Instant,
+}
+
+/// The DMA descriptors and encoded framebuffer used by `MatrixLcdCam`, which are statically
+/// allocated and so have to outlive it, to be reused when the peripheral is set up again
+#[derive(Debug)]
+pub struct MatrixLcdCamBuffers<const PANELS: usize = 2> {
+    refresh_chain: RefreshChain,
+    encoding: &'static mut DmaEncoding<PANELS>,
+}
+
+impl<const PANELS: usize> MatrixLcdCamBuffers<PANELS> {
+    /// Allocate the buffers. Panics if called more than once.
+    pub fn new() -> Self {
+        Self {
+            refresh_chain: RefreshChain::new(make_static!(
+                [DmaDescriptor::EMPTY; REFRESH_DESCRIPTORS * MAX_REFRESHES]
+            )),
+            encoding: make_static!(DmaEncoding::new()),
+        }
+    }
+}
+
+impl<const PANELS: usize> Default for MatrixLcdCamBuffers<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const PANELS: usize> MatrixLcdCam<'a, PANELS> {
+    /// Set up the driver, with the clock rate from `timing`. The rest of the timing is up to the
+    /// framebuffer. If the peripheral can't be set up, the buffers are handed back with the error.
+    pub fn new(
+        lcd_cam: LCD_CAM<'a>,
+        dma_channel: impl DmaChannelFor<LCD_CAM<'a>>,
+        MatrixPins {
+            sck,
+            sdo,
+            le_mod,
+            row0,
+            row1,
+            row2,
+            row3,
+            oe,
+        }: MatrixPins<'a>,
+        MatrixLcdCamBuffers {
+            refresh_chain,
+            encoding,
+        }: MatrixLcdCamBuffers<PANELS>,
+        timing: &DisplayTiming,
+        stats: &'a RefreshStats,
+    ) -> Result<Self, (RenderError, MatrixLcdCamBuffers<PANELS>)> {
+        // the data is set up on the falling edge of WR and the column drivers sample it on the
+        // rising edge, which is what the default clock mode does
+        let config = Config::default().with_frequency(Rate::from_khz(timing.clock_khz));
+        let lcd_cam = LcdCam::new(lcd_cam);
+        let i8080 = match I8080::new(lcd_cam.lcd, dma_channel, config) {
+            Ok(i8080) => i8080,
+            Err(e) => {
+                let buffers = MatrixLcdCamBuffers {
+                    refresh_chain,
+                    encoding,
+                };
+                return Err((RenderError::Config(e), buffers));
+            }
+        };
+        let mut i8080 = i8080
+            .with_wrx(sck)
+            .with_data0(row0)
+            .with_data1(row1)
+            .with_data2(row2)
+            .with_data3(row3)
+            .with_data4(le_mod)
+            .with_data5(sdo);
+        if let Some(oe) = oe {
+            i8080 = i8080.with_data6(oe);
+        }
+
+        Ok(MatrixLcdCam {
+            i8080: i8080.into_async(),
+            refresh_chain,
+            encoding,
+            config,
+            stats,
+            last_render: Instant::now(),
+        })
+    }
+
+    /// Shut the peripheral down, giving back the buffers so that it can be set up again with `new`
+    pub fn release(self) -> MatrixLcdCamBuffers<PANELS> {
+        MatrixLcdCamBuffers {
+            refresh_chain: self.refresh_chain,
+            encoding: self.encoding,
+        }
+    }
+}
+
+impl<const PANELS: usize> MatrixDriver<PANELS> for MatrixLcdCam<'_, PANELS> {
+    type Error = RenderError;
+
+    fn max_refreshes() -> usize {
+        MAX_REFRESHES
+    }
+
+    /// Refresh the display from the framebuffer `refreshes` times, as a single transfer.
+    ///
+    /// Panics if `refreshes` is more than `max_refreshes`.
+    async fn render(
+        self,
+        fb: &DmaFrameBuffer<PANELS>,
+        refreshes: usize,
+    ) -> Result<Self, (RenderError, Self)> {
+        assert!(refreshes <= MAX_REFRESHES);
+        let Self {
+            i8080,
+            mut refresh_chain,
+            encoding,
+            ..
+        } = self;
+        encoding.update(fb);
+        refresh_chain.point_at_once(&*encoding, refreshes);
+        let start = Instant::now();
+        let (result, i8080, refresh_chain) = match i8080.send(Command::<u8>::None, 0, refresh_chain)
+        {
+            Ok(mut xfer) => {
+                xfer.wait_for_done().await;
+                let (result, i8080, refresh_chain) = xfer.wait();
+                (result.map_err(RenderError::Dma), i8080, refresh_chain)
+            }
+            Err((e, i8080, refresh_chain)) => (Err(RenderError::Dma(e)), i8080, refresh_chain),
+        };
+        let now = Instant::now();
+        // measured from the end of the last render, so that the time spent restarting and swapping
+        // counts against the refresh rate
+        let (sending, elapsed) = (now - start, now - self.last_render);
+        let new_matrix = MatrixLcdCam {
+            i8080,
+            refresh_chain,
+            encoding,
+            last_render: now,
+            ..self
+        };
+        match result {
+            Ok(()) => {
+                new_matrix
+                    .stats
+                    .record_refreshes(refreshes, sending.as_micros(), elapsed.as_micros());
+                Ok(new_matrix)
+            }
+            Err(e) => {
+                new_matrix.stats.record_error();
+                Err((e, new_matrix))
+            }
+        }
+    }
+
+    fn supports_timing(timing: &DisplayTiming) -> bool {
+        CLOCK_KHZ.contains(&timing.clock_khz)
+    }
+
+    fn set_timing(&mut self, timing: &DisplayTiming) -> Result<(), RenderError> {
+        let config = self.config.with_frequency(Rate::from_khz(timing.clock_khz));
+        self.i8080
+            .apply_config(&config)
+            .map_err(RenderError::Config)?;
+        self.config = config;
+        Ok(())
+    }
+}
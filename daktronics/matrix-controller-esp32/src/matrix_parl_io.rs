@@ -1,7 +1,6 @@
 /// This code is in part based on liebman's esp-hub75 driver, which is copyright esp-rs 2021,
 /// licensed under `MIT OR Apache-2.0`.
 /// https://github.com/liebman/esp-hub75/blob/8c738d7977f640caebde9b985435b803206586ff/src/parl_io.rs
-/// https://github.com/liebman/esp-hub75/blob/8c738d7977f640caebde9b985435b803206586ff/src/framebuffer/plain.rs
 /// hub75 is a very similar protocol to what this led matrix uses, except:
 /// - hub75 has RGB channels for each pixel
 /// - hub75 addresses two rows at once by providing two sets of data inputs, so you still only need
@@ -9,235 +8,113 @@
 ///   addresses two rows at once by using N_COLS*2 clock cycles
 ///
 /// We're using the Parallel IO peripheral (PARL_IO) to set the states of multiple pins at once per
-/// clock cycle. We do still have to initiate a DMA transfer ourselves in a loop, so it's not just
-/// doing *everything* for us, but it's still several times faster than using DMA with the SPI
-/// peripheral to send over one row at a time (see `matrix_spi.rs` for an example of that)
-use bitfield::bitfield;
-use embedded_graphics::pixelcolor::Gray8;
-use embedded_graphics::prelude::*;
-use esp_hal::dma::{DmaChannelFor, DmaDescriptor, DmaTxBuf, ReadBuffer};
+/// clock cycle, which is several times faster than using DMA with the SPI peripheral to send over
+/// one row at a time (see `matrix_spi.rs` for an example of that).
+///
+/// A whole refresh (every bit plane, repeated as many times as its weight requires) is one DMA
+/// descriptor chain that loops back on itself (see `refresh_chain.rs`), so the CPU isn't involved
+/// while a transfer runs. The display doesn't refresh forever on its own though: the PARL_IO on the
+/// C6 only has a 16 bit byte counter, so a transfer ends after at most `max_refreshes` refreshes
+/// (two while new frames keep coming), and the render loop has to start the next one. The pins sit
+/// at `IDLE_VALUE` in between, which blanks the display. That is also when the framebuffer being
+/// shown gets encoded (only what changed since the last one gets rewritten), and the chain
+/// re-pointed at it.
+///
+/// The gap between transfers is a known limitation. Every refresh is complete and the gap is dark,
+/// so it dims every pixel by the same share rather than hurting the uniformity, but it does flicker
+/// at the restart rate. Both the restart rate and the share of time spent refreshing are in
+/// `RefreshStats`.
+///
+/// The framebuffer itself lives in `matrix_core::framebuffer`, and its encoding in
+/// `matrix_core::encoding`.
+use core::ops::RangeInclusive;
+use crate::driver::{MatrixDriver, MatrixPins};
+use crate::refresh_chain::{RefreshChain, REFRESH_DESCRIPTORS};
+use embassy_time::Instant;
+use esp_hal::dma::{DmaChannelFor, DmaDescriptor, DmaTxBuf, DmaTxBuffer};
 use esp_hal::dma_descriptors;
-use esp_hal::gpio::{AnyPin, NoPin};
+use esp_hal::gpio::{Input, NoPin};
 use esp_hal::parl_io::{
-    BitPackOrder, ClkOutPin, ParlIo, ParlIoTx, SampleEdge, TxConfig, TxEightBits,
+    BitPackOrder, ClkOutPin, ConfigError, ParlIo, ParlIoTx, SampleEdge, TxConfig, TxEightBits,
 };
 use esp_hal::peripherals::PARL_IO;
+use esp_hal::Async;
 use esp_hal::time::Rate;
-
-const ROWS: usize = 16 / 2;
-const COLS: usize = 96 * 2;
-const BITS: u8 = 3;
-
-// https://github.com/liebman/esp-hub75/blob/8c738d7977f640caebde9b985435b803206586ff/src/framebuffer/mod.rs#L79C5-L81C2
-const fn compute_frame_count(bits: u8) -> usize {
-    (1usize << bits) - 1
-}
-
-const FRAME_COUNT: usize = compute_frame_count(BITS);
-
-bitfield! {
-    /// An 8-bit word representing the control signals for a single pixel/clock cycle
-    #[derive(Clone, Copy, Default, PartialEq)]
-    #[repr(transparent)]
-    struct Entry(u8);
-    impl Debug;
-    unused1, set_unused1: 7;
-    unused0, set_unused0: 6;
-    value, set_value: 5;
-    le_mod, set_le_mod: 4;
-    output_blank, set_output_blank: 3;
-    row, set_row: 2, 0;
-}
-
-impl Entry {
-    fn set_color(&mut self, color: Gray8, brightness: u8) {
-        self.set_value(color.luma() > brightness);
-    }
-}
-
-const ROW_EXTRA: usize = 1;
-
-/// Represents a single row of pixels in the framebuffer.
-///
-/// This struct manages the control signals for the matrix (row, latch, etc) and sets them
-/// appropriately.
-#[derive(Clone, Copy, PartialEq, Debug)]
-#[repr(C)]
-struct Row {
-    data: [Entry; COLS + ROW_EXTRA],
-}
-
-const BLANKING_DELAY: usize = 25;
-
-impl Row {
-    pub fn format(&mut self, addr: u8, prev_addr: u8) {
-        let mut entry = Entry(0);
-        entry.set_row(prev_addr);
-        entry.set_output_blank(true);
-        entry.set_le_mod(false);
-        for x in 0..COLS {
-            // if we enable display too soon then we will have ghosting
-            if x == COLS - BLANKING_DELAY - 1 {
-                entry.set_output_blank(true);
-            } else if x == COLS - 1 {
-                entry.set_le_mod(true);
-            } else if x == 1 {
-                entry.set_output_blank(false);
-            }
-
-            self.data[x] = entry;
-        }
-        for e in 0..ROW_EXTRA {
-            if e == 0 {
-                entry.set_row(addr);
-                entry.set_le_mod(false);
-            }
-
-            self.data[COLS + e] = entry;
-        }
-    }
-}
-
-#[derive(Copy, Clone, Debug)]
-#[repr(C)]
-struct Frame {
-    rows: [Row; ROWS],
-}
-
-impl Frame {
-    pub fn format(&mut self) {
-        for (addr, row) in self.rows.iter_mut().enumerate() {
-            let prev_addr = if addr == 0 {
-                ROWS as u8 - 1
-            } else {
-                addr as u8 - 1
-            };
-            row.format(addr as u8, prev_addr);
-        }
-    }
-
-    // works with both types of "rows" (physical and virtual)
-    pub fn set_pixel(&mut self, y: usize, x: usize, color: Gray8, brightness: u8) {
-        let row = &mut self.rows[if y < ROWS { y } else { y - ROWS }];
-        row.data[if y < ROWS { x } else { x + (COLS / 2) }].set_color(color, brightness);
-    }
+use matrix_core::error_detection::{ErrorDetection, FaultMap, Test, SCAN_ROWS};
+use matrix_core::encoding::{DmaEncoding, IDLE_VALUE, MAX_TRANSFER_LEN};
+use matrix_core::framebuffer::{DmaFrameBuffer, MAX_PANELS};
+use matrix_core::timing::{DisplayTiming, RefreshStats};
+use static_cell::make_static;
+
+/// The PARL_IO byte counter is 16 bits, which limits how long a single transfer can be
+const MAX_PARL_IO_LEN: usize = u16::MAX as usize;
+const _: () = assert!(
+    DmaFrameBuffer::<MAX_PANELS>::refresh_len() <= MAX_PARL_IO_LEN,
+    "a refresh has to fit in a single transfer"
+);
+
+/// Clock rates that the 240 MHz PARL_IO clock can be divided down to (by at most 65535), up to the
+/// 40 MHz that the peripheral can output
+const CLOCK_KHZ: RangeInclusive<u32> = 4..=40_000;
+
+#[derive(Debug, defmt::Format)]
+pub enum RenderError {
+    ParlIo(esp_hal::parl_io::Error),
+    Dma(esp_hal::dma::DmaError),
+    Config(ConfigError),
 }
 
-type FbFrames = [Frame; FRAME_COUNT];
-
-const fn dma_buffer_size_bytes() -> usize {
-    size_of::<FbFrames>()
+/// The DMA descriptors and encoded framebuffer used by `MatrixParlIo`, which are statically
+/// allocated and so have to outlive it, to be reused when the peripheral is set up again
+#[derive(Debug)]
+pub struct MatrixParlIoBuffers<const PANELS: usize = 2> {
+    tx_descriptors: &'static mut [DmaDescriptor],
+    refresh_chain: RefreshChain,
+    encoding: &'static mut DmaEncoding<PANELS>,
 }
 
-const UPSIDE_DOWN: bool = true;
-
-#[derive(Copy, Clone)]
-#[repr(C)]
-pub struct DmaFrameBuffer {
-    _align: u64,
-    frames: FbFrames,
-}
-impl DmaFrameBuffer {
+impl<const PANELS: usize> MatrixParlIoBuffers<PANELS> {
+    /// Allocate the buffers. Panics if called more than once.
     pub fn new() -> Self {
-        let mut fb = Self {
-            _align: 0,
-            frames: [Frame {
-                rows: [Row {
-                    data: [Entry(0); _],
-                }; _],
-            }; _],
-        };
-        fb.clear();
-        fb
-    }
-
-    pub fn clear(&mut self) {
-        for frame in self.frames.iter_mut() {
-            frame.format();
-        }
-    }
-
-    pub fn set_pixel(&mut self, p: Point, color: Gray8) {
-        if p.x < 0 || p.y < 0 {
-            return;
-        }
-        self.set_pixel_internal(p.x as usize, p.y as usize, color);
-    }
-
-    pub fn set_pixel_internal(&mut self, x: usize, y: usize, color: Gray8) {
-        if x >= /*COLS * 2*/ 96 || y >= /*ROWS * 2*/ 16 {
-            return;
-        }
-        // set the pixel in all frames
-        for i in 0..FRAME_COUNT {
-            let brightness_step = 1 << (8 - BITS);
-            let brightness = (i as u8 + 1).saturating_mul(brightness_step);
-            self.frames[i].set_pixel(
-                if UPSIDE_DOWN { 15 - y } else { y },
-                if UPSIDE_DOWN { 95 - x } else { x },
-                color,
-                brightness,
-            );
-        }
-    }
-}
-
-impl OriginDimensions for DmaFrameBuffer {
-    fn size(&self) -> Size {
-        Size::new((COLS / 2) as u32, (ROWS * 2) as u32)
-    }
-}
-
-impl DrawTarget for DmaFrameBuffer {
-    type Color = Gray8;
-    type Error = core::convert::Infallible;
-
-    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
-    where
-        I: IntoIterator<Item = Pixel<Self::Color>>,
-    {
-        for pixel in pixels {
-            self.set_pixel_internal(pixel.0.x as usize, pixel.0.y as usize, pixel.1);
+        let (_, tx_descriptors) = dma_descriptors!(0, MAX_TRANSFER_LEN);
+        Self {
+            tx_descriptors,
+            refresh_chain: RefreshChain::new(make_static!(
+                [DmaDescriptor::EMPTY; REFRESH_DESCRIPTORS]
+            )),
+            encoding: make_static!(DmaEncoding::new()),
         }
-        Ok(())
     }
 }
 
-unsafe impl ReadBuffer for DmaFrameBuffer {
-    unsafe fn read_buffer(&self) -> (*const u8, usize) {
-        let ptr = &self.frames as *const _ as *const u8;
-        let len = size_of_val(&self.frames);
-        (ptr, len)
+impl<const PANELS: usize> Default for MatrixParlIoBuffers<PANELS> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[derive(Debug)]
-pub enum RenderError {
-    ParlIo(esp_hal::parl_io::Error),
-    Dma(esp_hal::dma::DmaError),
-}
-
-#[derive(Debug)]
-pub struct MatrixParlIo<'a> {
-    parl_io: ParlIoTx<'a, esp_hal::Async>,
+pub struct MatrixParlIo<'a, const PANELS: usize = 2> {
+    parl_io: ParlIoTx<'a, Async>,
     tx_descriptors: &'static mut [DmaDescriptor],
+    refresh_chain: RefreshChain,
+    /// The framebuffer being shown, encoded for the refresh chain to point at
+    encoding: &'static mut DmaEncoding<PANELS>,
+    config: TxConfig,
+    stats: &'a RefreshStats,
+    /// When the last `render` finished, for measuring the refresh rate
+    last_render: Instant,
+    /// Whether CD OE/SW/ED is wired, which error detection needs
+    oe_wired: bool,
 }
 
-pub struct MatrixParlIoPins<'a> {
-    pub sck: AnyPin<'a>,
-    pub sdo: AnyPin<'a>,
-    pub le_mod: AnyPin<'a>,
-    pub row0: AnyPin<'a>,
-    pub row1: AnyPin<'a>,
-    pub row2: AnyPin<'a>,
-    pub row3: AnyPin<'a>,
-}
-
-impl<'a> MatrixParlIo<'a> {
+impl<'a, const PANELS: usize> MatrixParlIo<'a, PANELS> {
+    /// Set up the driver, with the clock rate from `timing`. The rest of the timing is up to the
+    /// framebuffer. If the peripheral can't be set up, the buffers are handed back with the error.
     pub fn new(
         parl_io: PARL_IO<'a>,
         dma_channel: impl DmaChannelFor<PARL_IO<'a>>,
-        MatrixParlIoPins {
+        MatrixPins {
             sck,
             sdo,
             le_mod,
@@ -245,63 +122,208 @@ impl<'a> MatrixParlIo<'a> {
             row1,
             row2,
             row3,
-        }: MatrixParlIoPins<'a>,
-    ) -> Self {
-        let mut idle_value = Entry(0);
-        idle_value.set_output_blank(true);
+            oe,
+        }: MatrixPins<'a>,
+        MatrixParlIoBuffers {
+            tx_descriptors,
+            refresh_chain,
+            encoding,
+        }: MatrixParlIoBuffers<PANELS>,
+        timing: &DisplayTiming,
+        stats: &'a RefreshStats,
+    ) -> Result<Self, (RenderError, MatrixParlIoBuffers<PANELS>)> {
         let config = TxConfig::default()
-            .with_frequency(Rate::from_khz(1000))
-            .with_idle_value(idle_value.0 as u16) // the peripheral will send this in between finishing the DMA transfer and us sending the next one, so just turn off the display (in practice isn't much of a delay)
+            .with_frequency(Rate::from_khz(timing.clock_khz))
+            .with_idle_value(IDLE_VALUE as u16) // the peripheral will send this in between finishing the DMA transfer and us sending the next one, so just turn off the display (in practice isn't much of a delay)
             .with_sample_edge(SampleEdge::Invert)
             .with_bit_order(BitPackOrder::Msb);
+        let oe_wired = oe.is_some();
+        let pins = match oe {
+            Some(oe) => TxEightBits::new(row0, row1, row2, row3, le_mod, sdo, oe, NoPin),
+            None => TxEightBits::new(row0, row1, row2, row3, le_mod, sdo, NoPin, NoPin),
+        };
         let parl_io = ParlIo::new(parl_io, dma_channel)
-            .unwrap()
-            .into_async()
-            .tx
-            .with_config(
-                TxEightBits::new(row0, row1, row2, row3, le_mod, sdo, NoPin, NoPin),
-                ClkOutPin::new(sck),
-                config,
-            )
-            .unwrap();
+            .map_err(RenderError::ParlIo)
+            .and_then(|parl_io| {
+                parl_io
+                    .into_async()
+                    .tx
+                    .with_config(pins, ClkOutPin::new(sck), config.clone())
+                    .map_err(RenderError::Config)
+            });
+        let parl_io = match parl_io {
+            Ok(parl_io) => parl_io,
+            Err(e) => {
+                let buffers = MatrixParlIoBuffers {
+                    tx_descriptors,
+                    refresh_chain,
+                    encoding,
+                };
+                return Err((e, buffers));
+            }
+        };
 
-        let (_, tx_descriptors) = dma_descriptors!(0, dma_buffer_size_bytes());
-        MatrixParlIo {
+        Ok(MatrixParlIo {
             parl_io,
             tx_descriptors,
+            refresh_chain,
+            encoding,
+            config,
+            stats,
+            last_render: Instant::now(),
+            oe_wired,
+        })
+    }
+
+    /// Shut the peripheral down, giving back the buffers so that it can be set up again with `new`.
+    /// The pins are left in whatever state the last transfer left them in.
+    pub fn release(self) -> MatrixParlIoBuffers<PANELS> {
+        MatrixParlIoBuffers {
+            tx_descriptors: self.tx_descriptors,
+            refresh_chain: self.refresh_chain,
+            encoding: self.encoding,
         }
     }
 
-    pub async fn render(self, fb: &DmaFrameBuffer) -> Result<Self, (RenderError, Self)> {
+    /// Test every LED for open and short circuits, using the column drivers' error detection mode.
+    /// `sdo` is the SDO of the last panel in the chain, wired back to an input, and `fb` is only
+    /// used to map the results to pixels.
+    ///
+    /// This takes a few seconds, during which the display shows garbage, so it should be followed
+    /// by a normal `render`.
+    ///
+    /// Panics if `oe` wasn't wired up in `MatrixParlIoPins`.
+    pub async fn detect_faults(
+        mut self,
+        fb: &DmaFrameBuffer<PANELS>,
+        sdo: &Input<'_>,
+    ) -> Result<(Self, FaultMap<PANELS>), (RenderError, Self)> {
+        assert!(self.oe_wired, "error detection needs CD OE/SW/ED");
+        let mut detection = ErrorDetection::<PANELS>::new();
+        let mut faults = FaultMap::new();
+        for test in Test::ALL {
+            for row in 0..SCAN_ROWS {
+                self = self.transfer(detection.setup(row, test)).await?;
+                for bit in 0..ErrorDetection::<PANELS>::CHAIN_LEN {
+                    faults.record(fb, row, test, bit, sdo.is_high());
+                    self = self.transfer(detection.shift()).await?;
+                }
+                self = self.transfer(detection.finish()).await?;
+            }
+        }
+        Ok((self, faults))
+    }
+
+    /// Send a single buffer
+    async fn transfer(self, buffer: &[u8]) -> Result<Self, (RenderError, Self)> {
         let tx_buffer = unsafe {
-            let (ptr, len) = fb.read_buffer();
-            core::slice::from_raw_parts_mut(ptr as *mut u8, len)
+            core::slice::from_raw_parts_mut(buffer.as_ptr() as *mut u8, buffer.len())
         };
 
-        let tx_buf = DmaTxBuf::new(self.tx_descriptors, tx_buffer).unwrap();
-        let mut xfer = self
-            .parl_io
-            .write(tx_buf.len(), tx_buf)
-            .map_err(|(e, parl_io, buf)| {
-                let (tx_descriptors, _) = buf.split();
-                (
-                    RenderError::ParlIo(e),
-                    Self {
-                        parl_io,
-                        tx_descriptors,
-                    },
-                )
-            })?;
-        xfer.wait_for_done().await;
-        let (result, parl_io, tx_buf) = xfer.wait();
+        let Self {
+            parl_io,
+            tx_descriptors,
+            ..
+        } = self;
+        let tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer).unwrap();
+        let (result, parl_io, tx_buf) = write(parl_io, tx_buf.len(), tx_buf).await;
         let (tx_descriptors, _) = tx_buf.split();
         let new_matrix = MatrixParlIo {
             parl_io,
             tx_descriptors,
+            ..self
         };
         match result {
             Ok(()) => Ok(new_matrix),
-            Err(e) => Err((RenderError::Dma(e), new_matrix)),
+            Err(e) => Err((e, new_matrix)),
         }
     }
 }
+
+impl<const PANELS: usize> MatrixDriver<PANELS> for MatrixParlIo<'_, PANELS> {
+    type Error = RenderError;
+
+    fn max_refreshes() -> usize {
+        MAX_PARL_IO_LEN / DmaFrameBuffer::<PANELS>::refresh_len()
+    }
+
+    /// Refresh the display from the framebuffer `refreshes` times, as a single transfer. More
+    /// refreshes means fewer restarts, but the framebuffer can only be swapped in between.
+    ///
+    /// Panics if `refreshes` is more than `max_refreshes`.
+    async fn render(
+        self,
+        fb: &DmaFrameBuffer<PANELS>,
+        refreshes: usize,
+    ) -> Result<Self, (RenderError, Self)> {
+        assert!(refreshes <= <Self as MatrixDriver<PANELS>>::max_refreshes());
+        let Self {
+            parl_io,
+            mut refresh_chain,
+            encoding,
+            ..
+        } = self;
+        encoding.update(fb);
+        refresh_chain.point_at(&*encoding);
+        let len = DmaFrameBuffer::<PANELS>::refresh_len() * refreshes;
+        let start = Instant::now();
+        let (result, parl_io, refresh_chain) = write(parl_io, len, refresh_chain).await;
+        let now = Instant::now();
+        // measured from the end of the last render, so that the time spent restarting and swapping
+        // counts against the refresh rate
+        let (sending, elapsed) = (now - start, now - self.last_render);
+        let new_matrix = MatrixParlIo {
+            parl_io,
+            refresh_chain,
+            encoding,
+            last_render: now,
+            ..self
+        };
+        match result {
+            Ok(()) => {
+                new_matrix
+                    .stats
+                    .record_refreshes(refreshes, sending.as_micros(), elapsed.as_micros());
+                Ok(new_matrix)
+            }
+            Err(e) => {
+                new_matrix.stats.record_error();
+                Err((e, new_matrix))
+            }
+        }
+    }
+
+    fn supports_timing(timing: &DisplayTiming) -> bool {
+        CLOCK_KHZ.contains(&timing.clock_khz)
+    }
+
+    fn set_timing(&mut self, timing: &DisplayTiming) -> Result<(), RenderError> {
+        let config = self
+            .config
+            .clone()
+            .with_frequency(Rate::from_khz(timing.clock_khz));
+        self.parl_io
+            .apply_config(&config)
+            .map_err(RenderError::Config)?;
+        self.config = config;
+        Ok(())
+    }
+}
+
+/// Send `len` bytes from `buf` and wait for the transfer to finish
+async fn write<'a, B>(
+    parl_io: ParlIoTx<'a, Async>,
+    len: usize,
+    buf: B,
+) -> (Result<(), RenderError>, ParlIoTx<'a, Async>, B)
+where
+    B: DmaTxBuffer<Final = B>,
+{
+    let mut xfer = match parl_io.write(len, buf) {
+        Ok(xfer) => xfer,
+        Err((e, parl_io, buf)) => return (Err(RenderError::ParlIo(e)), parl_io, buf),
+    };
+    xfer.wait_for_done().await;
+    let (result, parl_io, buf) = xfer.wait();
+    (result.map_err(RenderError::Dma), parl_io, buf)
+}
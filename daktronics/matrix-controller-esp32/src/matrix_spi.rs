@@ -1,94 +1,175 @@
-// old spi driver
-// not used anymore but kept around for reference
-// might help you understand how the parl_io driver works!
-
-use core::array;
-use embassy_time::Timer;
+/// SPI driver, for ESP32 variants without PARL_IO. This is the original driver for the matrix, and
+/// it's a lot simpler than the PARL_IO one, so it might also help you understand how that works.
+///
+/// SPI can only drive the clock and data lines, so only the pixel data is sent with DMA, one scan
+/// row of one bit plane at a time. LE/MOD and the row decoder are driven as plain GPIOs in between,
+/// and each row is lit for the on-time of its bit plane with a timer, so the lowest bit planes are
+/// only as accurate as the timer is.
+use core::ops::RangeInclusive;
+use crate::driver::{MatrixDriver, MatrixPins};
+use embassy_time::{Instant, Timer};
 use esp_hal::dma::{DmaChannelFor, DmaRxBuf, DmaTxBuf};
 use esp_hal::dma_buffers;
-use esp_hal::gpio::{AnyPin, DriveMode, Level, Output, OutputConfig};
-use esp_hal::spi::master::{Config, Spi, SpiDmaBus};
+use esp_hal::gpio::{DriveMode, Level, Output, OutputConfig};
+use esp_hal::spi::master::{Config, ConfigError, Spi, SpiDmaBus};
 use esp_hal::spi::{AnySpi, Mode};
 use esp_hal::time::Rate;
+use matrix_core::framebuffer::{DmaFrameBuffer, BITS, MAX_PANELS, PANEL_CHAIN, ROWS};
+use matrix_core::timing::{DisplayTiming, RefreshStats};
 
-pub struct Matrix<'a> {
-    spi: SpiDmaBus<'a, esp_hal::Async>,
-    le_mod: Output<'a>,
-    rows: [Output<'a>; 4],
-    current_row: u8,
+/// Bytes of pixel data in a scan row of the longest chain
+const MAX_ROW_LEN: usize = MAX_PANELS * PANEL_CHAIN / 8;
+
+/// Clock rates that the 80 MHz SPI clock can be divided down to (by at most 1024), up to what a
+/// signal routed through the GPIO matrix can keep up with
+const CLOCK_KHZ: RangeInclusive<u32> = 79..=40_000;
+
+#[derive(Debug, defmt::Format)]
+pub enum RenderError {
+    Spi(esp_hal::spi::Error),
+    Config(ConfigError),
 }
 
-fn level_of_u8(n: u8) -> Level {
-    if n == 0 {
-        Level::Low
-    } else {
-        Level::High
-    }
+pub struct MatrixSpi<'a> {
+    spi: SpiDmaBus<'a, esp_hal::Async>,
+    config: Config,
+    le_mod: Output<'a>,
+    rows: [Output<'a>; 3],
+    blank: Output<'a>,
+    clock_khz: u32,
+    stats: &'a RefreshStats,
+    /// When the last `render` finished, for measuring the refresh rate
+    last_render: Instant,
 }
 
-impl<'a> Matrix<'a> {
+impl<'a> MatrixSpi<'a> {
+    /// Set up the driver, with the clock rate from `timing`. The rest of the timing is up to the
+    /// framebuffer.
     pub fn new(
         spi: AnySpi<'a>,
         dma_channel: impl DmaChannelFor<AnySpi<'a>>,
-        sck: AnyPin<'a>,
-        sdo: AnyPin<'a>,
-        le_mod: AnyPin<'a>,
-        rows: [AnyPin<'a>; 4],
-    ) -> Self {
+        MatrixPins {
+            sck,
+            sdo,
+            le_mod,
+            row0,
+            row1,
+            row2,
+            row3,
+            ..
+        }: MatrixPins<'a>,
+        timing: &DisplayTiming,
+        stats: &'a RefreshStats,
+    ) -> Result<Self, RenderError> {
         // we're not using rx dma (nothing to receive), but the spi api makes us make a buffer anyway
         // it fails if we make the rx buffer size 0, so we have to set it to 1
-        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(1, 192 / 8);
+        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(1, MAX_ROW_LEN);
         let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer).unwrap();
         let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer).unwrap();
 
-        let spi = Spi::new(
-            spi,
-            Config::default()
-                .with_frequency(Rate::from_khz(100))
-                .with_mode(Mode::_3), // to send bit, pull clock low and set sdo on falling edge
-        )
-        .unwrap()
-        .with_sck(sck)
-        .with_mosi(sdo)
-        .with_dma(dma_channel)
-        .with_buffers(dma_rx_buf, dma_tx_buf)
-        .into_async();
+        let config = Config::default()
+            .with_frequency(Rate::from_khz(timing.clock_khz))
+            .with_mode(Mode::_3); // to send bit, pull clock low and set sdo on falling edge
+        let spi = Spi::new(spi, config)
+            .map_err(RenderError::Config)?
+            .with_sck(sck)
+            .with_mosi(sdo)
+            .with_dma(dma_channel)
+            .with_buffers(dma_rx_buf, dma_tx_buf)
+            .into_async();
 
-        Self {
+        let output = |pin, level| Output::new(pin, level, OutputConfig::default());
+        Ok(Self {
             spi,
+            config,
             le_mod: Output::new(
                 le_mod,
-                Level::High,
+                Level::Low,
                 OutputConfig::default().with_drive_mode(DriveMode::PushPull),
             ),
-            rows: rows.map(|row| Output::new(row, Level::High, OutputConfig::default())),
-            current_row: 0,
-        }
+            rows: [row0, row1, row2].map(|row| output(row, Level::Low)),
+            blank: output(row3, Level::High),
+            clock_khz: timing.clock_khz,
+            stats,
+            last_render: Instant::now(),
+        })
     }
 
-    async fn render_row(&mut self, n: u8, data: &[u8; 192 / 8]) {
-        self.le_mod.set_low();
-        self.spi.write_async(data).await.unwrap();
-        self.rows[3].set_high();
-        self.rows[0].set_level(level_of_u8(n % 2));
-        self.rows[1].set_level(level_of_u8(n / 2 % 2));
-        self.rows[2].set_level(level_of_u8(n / 4 % 2));
+    /// Shift in scan row `row` while the display is blanked, then light it for `on_time_us` µs
+    async fn render_row(
+        &mut self,
+        row: usize,
+        data: &[u8],
+        on_time_us: u64,
+    ) -> Result<(), RenderError> {
+        self.spi.write_async(data).await.map_err(RenderError::Spi)?;
+        for (bit, pin) in self.rows.iter_mut().enumerate() {
+            pin.set_level(Level::from(row & (1 << bit) != 0));
+        }
         self.le_mod.set_high();
-        Timer::after_micros(50).await;
-        self.rows[3].set_low();
+        self.le_mod.set_low();
+        if on_time_us > 0 {
+            self.blank.set_low();
+            Timer::after_micros(on_time_us).await;
+            self.blank.set_high();
+        }
+        Ok(())
+    }
+
+    async fn refresh<const PANELS: usize>(
+        &mut self,
+        fb: &DmaFrameBuffer<PANELS>,
+    ) -> Result<(), RenderError> {
+        for plane in 0..BITS as usize {
+            let on_time_us = fb.plane_lit_cycles(plane) as u64 * 1000 / self.clock_khz as u64;
+            for row in 0..ROWS {
+                self.render_row(row, fb.row_data(plane, row), on_time_us)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const PANELS: usize> MatrixDriver<PANELS> for MatrixSpi<'_> {
+    type Error = RenderError;
+
+    /// Every row is its own transfer anyway, so there's nothing to gain from sending more
+    fn max_refreshes() -> usize {
+        1
+    }
+
+    async fn render(
+        mut self,
+        fb: &DmaFrameBuffer<PANELS>,
+        refreshes: usize,
+    ) -> Result<Self, (RenderError, Self)> {
+        let start = Instant::now();
+        for _ in 0..refreshes {
+            if let Err(e) = self.refresh(fb).await {
+                self.stats.record_error();
+                return Err((e, self));
+            }
+        }
+        let now = Instant::now();
+        self.stats.record_refreshes(
+            refreshes,
+            (now - start).as_micros(),
+            (now - self.last_render).as_micros(),
+        );
+        self.last_render = now;
+        Ok(self)
+    }
+
+    fn supports_timing(timing: &DisplayTiming) -> bool {
+        CLOCK_KHZ.contains(&timing.clock_khz)
     }
 
-    // render the next two physical rows of the display
-    pub async fn render_buffer(&mut self, buffer: [u8; 96 * 16]) {
-        let buffer_pos: usize = (self.current_row * 192) as usize;
-        // used to have a t*do for brightness support but this driver is just being kept around
-        // for documentation purposes now
-        let row_data: [u8; 192 / 8] = array::from_fn(|i| {
-            buffer[(buffer_pos + i * 8)..(buffer_pos + (i + 1) * 8)]
-                .iter()
-                .fold(0u8, |acc, cur| (acc << 1) | (*cur > 127) as u8)
-        });
-        self.render_row(self.current_row, &row_data).await;
-        self.current_row = (self.current_row + 1) % 8;
+    fn set_timing(&mut self, timing: &DisplayTiming) -> Result<(), RenderError> {
+        let config = self.config.with_frequency(Rate::from_khz(timing.clock_khz));
+        self.spi.apply_config(&config).map_err(RenderError::Config)?;
+        self.config = config;
+        self.clock_khz = timing.clock_khz;
+        Ok(())
     }
 }
@@ -1,19 +1,25 @@
 use crate::captive::spawn_captive_portal;
-use crate::config::{ConfigStore, PW_STORE_ID, SSID_STORE_ID};
+use crate::config::{flash_config_store, ConfigError, PW_STORE_ID, SSID_STORE_ID};
+use crate::http::http_task;
 use crate::net_utils::{net_task, wait_for_network_ready};
+use crate::watchdog;
 use defmt::{error, info};
 use embassy_executor::Spawner;
 use embassy_net::StackResources;
-use embassy_time::Timer;
+use embassy_time::{with_timeout, Duration, Timer};
 use esp_hal::peripherals::{RADIO_CLK, TIMG0, WIFI};
 use esp_hal::rng::Rng;
 use esp_hal::timer::timg::TimerGroup;
 use esp_storage::FlashStorageError;
 use esp_wifi::wifi::{ClientConfiguration, Configuration, WifiController, WifiEvent, WifiState};
 use esp_wifi::{wifi, EspWifiController};
+use matrix_core::watchdog::Subsystem;
 use static_cell::make_static;
 // https://github.com/esp-rs/esp-hal/blob/main/examples/src/bin/wifi_embassy_access_point.rs
 
+/// How often `connection` checks in with the watchdog while the connection is up
+const CHECK_IN_INTERVAL: Duration = Duration::from_secs(10);
+
 pub async fn net_init(
     spawner: &Spawner,
     timg0: TimerGroup<'static, TIMG0<'static>>,
@@ -27,8 +33,8 @@ pub async fn net_init(
 
     let rng_seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
-    let mut config_store = ConfigStore::new();
-    let ssid: Result<_, FlashStorageError> = try {
+    let mut config_store = flash_config_store();
+    let ssid: Result<_, ConfigError<FlashStorageError>> = try {
         (
             config_store.get(SSID_STORE_ID)?,
             config_store.get(PW_STORE_ID)?,
@@ -57,9 +63,12 @@ pub async fn net_init(
         .unwrap();
 
     info!("Starting controller in STA mode");
+    watchdog::check_in(Subsystem::Network);
     controller.start_async().await.unwrap();
     if let Err(e) = controller.connect_async().await {
         info!("Failed to connect to network: {:?}", e);
+        // the captive portal just waits for requests
+        watchdog::pause(Subsystem::Network);
         spawn_captive_portal(spawner, rng_seed, interfaces.ap, controller).await;
         return;
     }
@@ -77,15 +86,28 @@ pub async fn net_init(
 
     let ip_config = net_stack.config_v4().unwrap();
     info!("Got IP {}", ip_config.address);
+    spawner.spawn(http_task(net_stack, false)).ok();
 }
 
 #[embassy_executor::task]
 async fn connection(mut controller: WifiController<'static>) {
     loop {
+        watchdog::check_in(Subsystem::Network);
         match wifi::sta_state() {
             WifiState::StaConnected => {
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
+                // wake up every so often to check in, so that only a hung connect counts as a
+                // stall
+                while with_timeout(
+                    CHECK_IN_INTERVAL,
+                    controller.wait_for_event(WifiEvent::StaDisconnected),
+                )
+                .await
+                .is_err()
+                {
+                    watchdog::check_in(Subsystem::Network);
+                }
                 Timer::after_millis(5000).await;
+                watchdog::check_in(Subsystem::Network);
             }
             _ => {}
         }
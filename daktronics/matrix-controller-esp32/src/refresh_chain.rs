@@ -0,0 +1,106 @@
+/// DMA descriptor chains pointing straight at the bit planes of a `DmaEncoding`, shared by the
+/// drivers that send the raw `Entry` byte stream (PARL_IO and LCD_CAM).
+use esp_hal::dma::{
+    BurstConfig, DmaDescriptor, DmaTxBuffer, Owner, Preparation, TransferDirection,
+};
+use matrix_core::encoding::{DmaEncoding, MAX_TRANSFER_LEN};
+use matrix_core::framebuffer::REFRESH_TRANSFERS;
+
+/// Most data a single DMA descriptor can point to
+const DESCRIPTOR_CHUNK: usize = 4092;
+/// Descriptors needed for a full refresh of the largest framebuffer, including the tail, which is
+/// a single row
+pub(crate) const REFRESH_DESCRIPTORS: usize =
+    REFRESH_TRANSFERS * MAX_TRANSFER_LEN.div_ceil(DESCRIPTOR_CHUNK) + 1;
+
+/// A DMA descriptor chain covering full refreshes of an encoded framebuffer. Repeated bit planes just get
+/// more descriptors pointing at the same data.
+#[derive(Debug)]
+pub(crate) struct RefreshChain {
+    descriptors: &'static mut [DmaDescriptor],
+}
+
+impl RefreshChain {
+    /// Use `descriptors` for the chain, which needs `REFRESH_DESCRIPTORS` of them per refresh
+    pub fn new(descriptors: &'static mut [DmaDescriptor]) -> Self {
+        Self { descriptors }
+    }
+
+    /// Point the chain at `refreshes` refreshes of `encoding`, which has to stay put (and unchanged)
+    /// for as long as the chain is in use. Returns the number of descriptors used.
+    fn fill<const PANELS: usize>(
+        &mut self,
+        encoding: &DmaEncoding<PANELS>,
+        refreshes: usize,
+    ) -> usize {
+        let mut chunks = (0..refreshes).flat_map(|_| {
+            encoding
+                .transfers()
+                .flat_map(|transfer| transfer.chunks(DESCRIPTOR_CHUNK))
+        });
+        let mut len = 0;
+        for (descriptor, chunk) in self.descriptors.iter_mut().zip(chunks.by_ref()) {
+            descriptor.buffer = chunk.as_ptr() as *mut u8;
+            descriptor.set_size(chunk.len());
+            descriptor.set_length(chunk.len());
+            descriptor.set_owner(Owner::Dma);
+            descriptor.set_suc_eof(false);
+            len += 1;
+        }
+        assert!(chunks.next().is_none(), "not enough descriptors");
+        len
+    }
+
+    /// Point the chain at a single refresh of `encoding`, with the last descriptor linking back to
+    /// the first so that the DMA keeps going for as long as the peripheral wants data
+    pub fn point_at<const PANELS: usize>(&mut self, encoding: &DmaEncoding<PANELS>) {
+        let len = self.fill(encoding, 1);
+        let first = self.descriptors.as_mut_ptr();
+        for (i, descriptor) in self.descriptors[..len].iter_mut().enumerate() {
+            descriptor.next = first.wrapping_add((i + 1) % len);
+        }
+    }
+
+    /// Point the chain at `refreshes` refreshes of `encoding` back to back, ending with an EOF, for
+    /// peripherals that send until the DMA runs out
+    pub fn point_at_once<const PANELS: usize>(
+        &mut self,
+        encoding: &DmaEncoding<PANELS>,
+        refreshes: usize,
+    ) {
+        let len = self.fill(encoding, refreshes);
+        let first = self.descriptors.as_mut_ptr();
+        for (i, descriptor) in self.descriptors[..len].iter_mut().enumerate() {
+            descriptor.next = if i + 1 < len {
+                first.wrapping_add(i + 1)
+            } else {
+                core::ptr::null_mut()
+            };
+        }
+        self.descriptors[len - 1].set_suc_eof(true);
+    }
+}
+
+unsafe impl DmaTxBuffer for RefreshChain {
+    type View = RefreshChain;
+    type Final = RefreshChain;
+
+    fn prepare(&mut self) -> Preparation {
+        Preparation {
+            start: self.descriptors.as_mut_ptr(),
+            direction: TransferDirection::Out,
+            burst_transfer: BurstConfig::default(),
+            // the DMA would give the descriptors back to the CPU on the first lap otherwise
+            check_owner: Some(false),
+            auto_write_back: false,
+        }
+    }
+
+    fn into_view(self) -> Self::View {
+        self
+    }
+
+    fn from_view(view: Self::View) -> Self::Final {
+        view
+    }
+}
@@ -0,0 +1,81 @@
+/// Watchdog supervisor, standing in for the AF-6700's watchdog board. The render loop, the network
+/// and each content source check in with `check_in`, and `supervise` only feeds the timer group's
+/// hardware watchdog (MWDT) while all of them keep doing so. When one stalls, it records which in
+/// retained memory and resets the chip; if the executor running `supervise` itself hangs, the
+/// MWDT resets the chip without a record.
+use core::sync::atomic::{AtomicU8, Ordering};
+use defmt::{error, info, warn};
+use embassy_time::{Instant, Timer};
+use esp_hal::peripherals::TIMG1;
+use esp_hal::ram;
+use esp_hal::time::Duration;
+use esp_hal::timer::timg::{MwdtStage, Wdt};
+use matrix_core::watchdog::{Subsystem, Supervisor};
+
+/// How long the MWDT waits for `supervise` before resetting the chip
+const HARDWARE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often `supervise` checks the subsystems and feeds the MWDT
+const FEED_INTERVAL_MS: u64 = 500;
+
+/// Marks `STALL_RECORD` as valid, since retained memory holds garbage after a power-on
+const RECORD_MAGIC: u32 = 0x57d0_9000;
+
+static SUPERVISOR: Supervisor = Supervisor::new();
+
+/// `RECORD_MAGIC` plus the subsystem that stalled, left behind for the next boot
+#[ram(rtc_fast, persistent)]
+static mut STALL_RECORD: u32 = 0;
+
+/// The subsystem that stalled before the last reset, read out of `STALL_RECORD` by `init`
+static LAST_STALL: AtomicU8 = AtomicU8::new(u8::MAX);
+
+fn now_ms() -> u32 {
+    // only differences matter, so wrapping is fine
+    Instant::now().as_millis() as u32
+}
+
+pub fn check_in(subsystem: Subsystem) {
+    SUPERVISOR.check_in(subsystem, now_ms());
+}
+
+/// Stop watching `subsystem` until it next checks in
+pub fn pause(subsystem: Subsystem) {
+    SUPERVISOR.pause(subsystem);
+}
+
+/// Read and clear the record of the last stall. Call once at startup.
+pub fn init() {
+    let record = unsafe { core::ptr::addr_of_mut!(STALL_RECORD).replace(0) };
+    if record & !0xff == RECORD_MAGIC
+        && let Some(subsystem) = Subsystem::from_u8(record as u8)
+    {
+        warn!(
+            "reset by the watchdog after the {} subsystem stalled",
+            subsystem.name()
+        );
+        LAST_STALL.store(subsystem as u8, Ordering::Relaxed);
+    }
+}
+
+/// The subsystem whose stall caused the last reset, if that's why the chip was reset
+pub fn last_stall() -> Option<Subsystem> {
+    Subsystem::from_u8(LAST_STALL.load(Ordering::Relaxed))
+}
+
+#[embassy_executor::task]
+pub async fn supervise(mut wdt: Wdt<TIMG1<'static>>) {
+    wdt.set_timeout(MwdtStage::Stage0, HARDWARE_TIMEOUT);
+    wdt.enable();
+    info!("watchdog enabled");
+    loop {
+        if let Some(subsystem) = SUPERVISOR.stalled(now_ms()) {
+            error!("the {} subsystem stalled, resetting", subsystem.name());
+            unsafe {
+                core::ptr::addr_of_mut!(STALL_RECORD).write(RECORD_MAGIC | subsystem as u32);
+            }
+            esp_hal::system::software_reset();
+        }
+        wdt.feed();
+        Timer::after_millis(FEED_INTERVAL_MS).await;
+    }
+}
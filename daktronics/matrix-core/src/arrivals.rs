@@ -0,0 +1,284 @@
+/// Transit arrivals board layout, in the two-line AF-6700 style: the destination on the top line
+/// and the next arrival times on the bottom one, e.g. `To S.Waterfront` over `1 min & 15 min`.
+///
+/// Every AF-6700 assembly's worth of width shows one route, so a single assembly shows one at a
+/// time and longer chains show several side by side. When there are more routes than that, they're
+/// shown a page at a time. Lines that are too long for their column pause, scroll to the end,
+/// pause again, and the page stays up until they've finished.
+///
+/// The board remembers what it last drew, so only the lines that have changed or scrolled since
+/// are redrawn, and nothing at all in between.
+use crate::framebuffer::PANEL_WIDTH;
+use core::fmt::Write;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+/// Longest route name, e.g. `MAX Red`
+pub const ROUTE_LEN: usize = 16;
+/// Longest headsign
+pub const HEADSIGN_LEN: usize = 48;
+/// Predictions kept per arrival, which is as many as the bottom line shows
+pub const MAX_TIMES: usize = 2;
+/// Longest line of text
+const LINE_LEN: usize = ROUTE_LEN + HEADSIGN_LEN + 4;
+
+/// Width of the column each route gets: one AF-6700 assembly
+const COLUMN_WIDTH: u32 = PANEL_WIDTH as u32 * 2;
+/// Top of each line. The font's top row is blank, so the top line starts off the display, and the
+/// bottom line overlaps the top line's descenders by a row. Each line is erased from the row below
+/// its top, so erasing one never touches the other.
+const LINE_TOPS: [i32; 2] = [-1, 8];
+/// Shortest time a page is shown for
+const PAGE_MS: u32 = 5000;
+/// How long a long line stays put before and after scrolling
+const SCROLL_PAUSE_MS: u32 = 1500;
+/// How long a long line takes to scroll by a pixel
+const SCROLL_MS_PER_PIXEL: u32 = 40;
+
+/// Anything unusual about an arrival
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ArrivalStatus {
+    /// Arriving at the predicted times
+    #[default]
+    OnTime,
+    /// Arriving now, whatever the first prediction says
+    Due,
+    /// Running late. The predictions are shown after `Delayed`.
+    Delayed,
+    /// Not running, so the predictions aren't shown
+    Cancelled,
+}
+
+/// The next arrivals of a route at the stop
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Arrival {
+    /// Route name, which can be empty if the stop only has the one route
+    pub route: heapless::String<ROUTE_LEN>,
+    /// Where it's going
+    pub headsign: heapless::String<HEADSIGN_LEN>,
+    /// Predicted minutes until each arrival, soonest first
+    pub minutes: heapless::Vec<u16, MAX_TIMES>,
+    pub status: ArrivalStatus,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ArrivalTooLong;
+
+impl Arrival {
+    /// An arrival of `route` towards `headsign` in each of `minutes`. Only the first `MAX_TIMES`
+    /// predictions are kept.
+    pub fn new(
+        route: &str,
+        headsign: &str,
+        minutes: &[u16],
+        status: ArrivalStatus,
+    ) -> Result<Self, ArrivalTooLong> {
+        Ok(Self {
+            route: route.try_into().map_err(|_| ArrivalTooLong)?,
+            headsign: headsign.try_into().map_err(|_| ArrivalTooLong)?,
+            minutes: minutes.iter().copied().take(MAX_TIMES).collect(),
+            status,
+        })
+    }
+
+    /// The top line, e.g. `20 To Beaverton TC`, or just `To Beaverton TC` without a route name
+    pub fn destination(&self) -> heapless::String<LINE_LEN> {
+        let mut line = heapless::String::new();
+        if !self.route.is_empty() {
+            let _ = write!(line, "{} ", self.route);
+        }
+        let _ = write!(line, "To {}", self.headsign);
+        line
+    }
+
+    /// The bottom line: up to two times, e.g. `1 min & 15 min`, where a time of 0 is `Due`,
+    /// prefixed with `Delayed` if it's running late, or just `Cancelled`
+    pub fn times(&self) -> heapless::String<LINE_LEN> {
+        let mut line = heapless::String::new();
+        let none = self.minutes.is_empty();
+        let _ = match self.status {
+            ArrivalStatus::Cancelled => write!(line, "Cancelled"),
+            ArrivalStatus::Delayed => write!(line, "Delayed"),
+            ArrivalStatus::Due if none => write!(line, "Due"),
+            ArrivalStatus::OnTime if none => write!(line, "No arrivals"),
+            ArrivalStatus::OnTime | ArrivalStatus::Due => Ok(()),
+        };
+        if self.status == ArrivalStatus::Cancelled {
+            return line;
+        }
+        for (i, &minutes) in self.minutes.iter().enumerate() {
+            let separator = match i {
+                0 if line.is_empty() => "",
+                0 => " ",
+                _ => " & ",
+            };
+            let _ = if minutes == 0 || (i == 0 && self.status == ArrivalStatus::Due) {
+                write!(line, "{separator}Due")
+            } else {
+                write!(line, "{separator}{minutes} min")
+            };
+        }
+        line
+    }
+}
+
+/// Lays out up to `N` routes' arrivals, paging through them and scrolling long lines
+#[derive(Clone, Debug)]
+pub struct ArrivalsBoard<const N: usize = 8> {
+    arrivals: heapless::Vec<Arrival, N>,
+    /// Index of the first route on the page being shown
+    first: usize,
+    /// When the page went up, in ms
+    page_start_ms: Option<u32>,
+    /// The area that `drawn` was drawn in, or `None` if the whole board needs drawing
+    drawn_area: Option<Rectangle>,
+    /// Where each line of each route on the page was drawn, or `None` if it needs drawing
+    drawn: heapless::Vec<[Option<i32>; 2], N>,
+}
+
+impl<const N: usize> ArrivalsBoard<N> {
+    /// A board with no arrivals, which shows nothing
+    pub fn new() -> Self {
+        Self {
+            arrivals: heapless::Vec::new(),
+            first: 0,
+            page_start_ms: None,
+            drawn_area: None,
+            drawn: heapless::Vec::new(),
+        }
+    }
+
+    pub fn arrivals(&self) -> &[Arrival] {
+        &self.arrivals
+    }
+
+    /// Replace the arrivals with fresh predictions. Only the first `N` are kept. The page being
+    /// shown stays up, unless there are no longer enough routes for it, and only its lines that
+    /// read differently are redrawn.
+    pub fn set_arrivals(&mut self, arrivals: impl IntoIterator<Item = Arrival>) {
+        let arrivals: heapless::Vec<_, N> = arrivals.into_iter().take(N).collect();
+        for (column, drawn) in self.drawn.iter_mut().enumerate() {
+            let old = self.arrivals.get(self.first + column);
+            match (old, arrivals.get(self.first + column)) {
+                (Some(old), Some(new)) => {
+                    if old.destination() != new.destination() {
+                        drawn[0] = None;
+                    }
+                    if old.times() != new.times() {
+                        drawn[1] = None;
+                    }
+                }
+                // a route that's gone leaves its column to be erased
+                _ => self.drawn_area = None,
+            }
+        }
+        self.arrivals = arrivals;
+        if self.first >= self.arrivals.len() {
+            self.first = 0;
+            self.page_start_ms = None;
+            self.drawn_area = None;
+        }
+    }
+
+    /// Draw the board as it should look at `now_ms`, moving on to the next page once the current
+    /// one has been up for long enough. The first draw, and the first of every page, covers the
+    /// whole of `target`. After that, only the lines that have changed are, and this returns
+    /// whether anything was drawn.
+    pub fn draw<D: DrawTarget<Color = Gray8>>(
+        &mut self,
+        target: &mut D,
+        now_ms: u32,
+    ) -> Result<bool, D::Error> {
+        let area = target.bounding_box();
+        let columns = (area.size.width / COLUMN_WIDTH).max(1) as usize;
+        let column_width = area.size.width / columns as u32;
+
+        let start = *self.page_start_ms.get_or_insert(now_ms);
+        if now_ms.wrapping_sub(start) >= self.page_ms(columns, column_width) {
+            self.first += columns;
+            if self.first >= self.arrivals.len() {
+                self.first = 0;
+            }
+            self.page_start_ms = Some(now_ms);
+            self.drawn_area = None;
+        }
+        let elapsed = now_ms.wrapping_sub(self.page_start_ms.unwrap());
+
+        let mut changed = false;
+        if self.drawn_area != Some(area) {
+            target.fill_solid(&area, Gray8::BLACK)?;
+            self.drawn.clear();
+            self.drawn_area = Some(area);
+            changed = true;
+        }
+        let style = MonoTextStyle::new(&FONT_6X10, Gray8::WHITE);
+        let page = self.arrivals.iter().skip(self.first).take(columns);
+        for (column, arrival) in page.enumerate() {
+            if self.drawn.len() <= column {
+                let _ = self.drawn.push([None; 2]);
+            }
+            let left = area.top_left.x + (column as u32 * column_width) as i32;
+            for ((line, top), drawn) in [arrival.destination(), arrival.times()]
+                .iter()
+                .zip(LINE_TOPS)
+                .zip(&mut self.drawn[column])
+            {
+                let width = text_width(line);
+                let x = if width <= column_width {
+                    (column_width - width) as i32 / 2
+                } else {
+                    -(scroll_offset(width - column_width, elapsed) as i32)
+                };
+                if *drawn == Some(x) {
+                    continue;
+                }
+                let band = Rectangle::new(
+                    Point::new(left, area.top_left.y + top + 1),
+                    Size::new(column_width, FONT_6X10.character_size.height - 1),
+                );
+                let mut clipped = target.clipped(&band);
+                clipped.fill_solid(&band, Gray8::BLACK)?;
+                let position = Point::new(left + x, area.top_left.y + top);
+                Text::with_baseline(line, position, style, Baseline::Top).draw(&mut clipped)?;
+                *drawn = Some(x);
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// The routes on the page being shown
+    fn page(&self, columns: usize) -> impl Iterator<Item = &Arrival> {
+        self.arrivals.iter().skip(self.first).take(columns)
+    }
+
+    /// How long the page being shown stays up for: `PAGE_MS`, or as long as its longest line
+    /// takes to scroll
+    fn page_ms(&self, columns: usize, column_width: u32) -> u32 {
+        self.page(columns)
+            .flat_map(|arrival| [arrival.destination(), arrival.times()])
+            .map(|line| text_width(&line).saturating_sub(column_width))
+            .filter(|&overflow| overflow > 0)
+            .map(|overflow| 2 * SCROLL_PAUSE_MS + overflow * SCROLL_MS_PER_PIXEL)
+            .fold(PAGE_MS, u32::max)
+    }
+}
+
+impl<const N: usize> Default for ArrivalsBoard<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * FONT_6X10.character_size.width
+}
+
+/// How far a line that's `overflow` pixels too long has scrolled `elapsed_ms` into the page
+fn scroll_offset(overflow: u32, elapsed_ms: u32) -> u32 {
+    (elapsed_ms.saturating_sub(SCROLL_PAUSE_MS) / SCROLL_MS_PER_PIXEL).min(overflow)
+}
@@ -0,0 +1,178 @@
+/// Brightness uniformity calibration. Panels from different batches, and the columns driven by
+/// different MBI5169s, don't come out equally bright at the same level. A `GainMap` holds a gain
+/// for every pixel, which scales its intensity when it's quantized, so that the brighter parts of
+/// the display can be turned down to match the dimmest.
+///
+/// To measure it, show the calibration pattern (`draw_pattern`), photograph it, and turn down
+/// whatever comes out brighter than the rest, a panel, a column driver or a column at a time
+/// (`GainMap::apply_form`). Gains are by position on the display as it's drawn to, so the map has
+/// to be measured again if the orientation changes.
+use crate::form::{decode_field, form_field};
+use crate::framebuffer::{MAX_PANELS, PANEL_HEIGHT, PANEL_WIDTH, ROWS};
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_storage::{ReadStorage, Storage};
+
+/// The LEDs driven by one MBI5169: 8 columns, in one half of a panel (see `draw_pattern`)
+pub const PATCH: Size = Size::new(8, ROWS as u32);
+
+/// The gain that leaves a pixel as it is
+pub const UNITY_GAIN: u8 = u8::MAX;
+
+/// Marks a saved gain map, so that erased flash reads as no map
+const MAGIC: [u8; 4] = *b"GAIN";
+
+/// A gain for every pixel of a chain of `PANELS` panels, from 0 (off) to `UNITY_GAIN`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GainMap<const PANELS: usize = 2> {
+    gains: [[[u8; PANEL_WIDTH]; PANELS]; PANEL_HEIGHT],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidGains;
+
+impl<const PANELS: usize> GainMap<PANELS> {
+    pub const WIDTH: usize = PANELS * PANEL_WIDTH;
+    pub const HEIGHT: usize = PANEL_HEIGHT;
+
+    /// A map that leaves every pixel as it is
+    pub const fn new() -> Self {
+        Self {
+            gains: [[[UNITY_GAIN; PANEL_WIDTH]; PANELS]; PANEL_HEIGHT],
+        }
+    }
+
+    pub fn gain(&self, x: usize, y: usize) -> u8 {
+        self.gains[y][x / PANEL_WIDTH][x % PANEL_WIDTH]
+    }
+
+    /// Set the gain of every pixel in `area`, e.g. a whole panel or a column
+    pub fn fill(&mut self, area: &Rectangle, gain: u8) {
+        let size = Size::new(Self::WIDTH as u32, Self::HEIGHT as u32);
+        for p in area
+            .intersection(&Rectangle::new(Point::zero(), size))
+            .points()
+        {
+            let (x, y) = (p.x as usize, p.y as usize);
+            self.gains[y][x / PANEL_WIDTH][x % PANEL_WIDTH] = gain;
+        }
+    }
+
+    /// Set the gains of row `y`, from the left. `gains` can be shorter than the row.
+    pub fn set_row(&mut self, y: usize, gains: &[u8]) -> Result<(), InvalidGains> {
+        let row = self
+            .gains
+            .get_mut(y)
+            .ok_or(InvalidGains)?
+            .as_flattened_mut();
+        row.get_mut(..gains.len())
+            .ok_or(InvalidGains)?
+            .copy_from_slice(gains);
+        Ok(())
+    }
+
+    /// Change the gains from the fields of a urlencoded form, which has one of:
+    /// - `area=x,y,width,height&gain=g`: set every pixel in an area, e.g. `area=48,0,48,16&gain=230`
+    ///   for the second panel
+    /// - `row=y&gains=...`: set a row of pixels from the left, as two hex digits per pixel
+    ///
+    /// Returns `Ok(false)` if there aren't any gains in the form.
+    pub fn apply_form(&mut self, form: &str) -> Result<bool, InvalidGains> {
+        if let Some(area) = form_field(form, "area") {
+            let area = decode_field::<32>(area).ok_or(InvalidGains)?;
+            let mut fields = area.split(',').map(|field| field.parse::<u32>());
+            let mut next = || fields.next().ok_or(InvalidGains)?.map_err(|_| InvalidGains);
+            let (x, y) = (next()? as i32, next()? as i32);
+            let area = Rectangle::new(Point::new(x, y), Size::new(next()?, next()?));
+            let gain = form_field(form, "gain").ok_or(InvalidGains)?;
+            self.fill(&area, gain.parse().map_err(|_| InvalidGains)?);
+            Ok(true)
+        } else if let Some(row) = form_field(form, "row") {
+            let y = row.parse().map_err(|_| InvalidGains)?;
+            let hex = form_field(form, "gains").ok_or(InvalidGains)?;
+            if hex.len() % 2 != 0 {
+                return Err(InvalidGains);
+            }
+            let mut gains = [0; PANEL_WIDTH * MAX_PANELS];
+            let gains = gains.get_mut(..hex.len() / 2).ok_or(InvalidGains)?;
+            for (gain, digits) in gains.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+                let digits = core::str::from_utf8(digits).map_err(|_| InvalidGains)?;
+                *gain = u8::from_str_radix(digits, 16).map_err(|_| InvalidGains)?;
+            }
+            self.set_row(y, gains)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Load a map that was saved with `save` at `address`, or `None` if there isn't one for this
+    /// many panels
+    pub fn load<S: ReadStorage>(storage: &mut S, address: u32) -> Result<Option<Self>, S::Error> {
+        let mut header = [0; 8];
+        storage.read(address, &mut header)?;
+        if header[..4] != MAGIC || header[4..] != (PANELS as u32).to_le_bytes() {
+            return Ok(None);
+        }
+        let mut map = Self::new();
+        storage.read(address + header.len() as u32, map.as_bytes_mut())?;
+        Ok(Some(map))
+    }
+
+    pub fn save<S: Storage>(&self, storage: &mut S, address: u32) -> Result<(), S::Error> {
+        let mut header = [0; 8];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..].copy_from_slice(&(PANELS as u32).to_le_bytes());
+        storage.write(address, &header)?;
+        storage.write(
+            address + header.len() as u32,
+            self.gains.as_flattened().as_flattened(),
+        )
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.gains.as_flattened_mut().as_flattened_mut()
+    }
+}
+
+impl<const PANELS: usize> Default for GainMap<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scale `value` by `gain`, which is exact for `UNITY_GAIN`
+pub(crate) fn apply_gain(value: u16, gain: u8) -> u16 {
+    ((value as u32 * (gain as u32 + 1)) >> 8) as u16
+}
+
+/// Draw the calibration pattern at `luma`, to photograph and compare the brightness of its parts.
+/// Around half brightness shows the differences best, since the camera doesn't clip.
+///
+/// The display is split into `PATCH`es, each one the LEDs of a single column driver, with a dark
+/// marker pixel in the top left corner of each so that the photo can be lined up with the areas
+/// `GainMap::apply_form` takes (`area=x,y,8,8`). The first patch of every panel has its panel
+/// number marked too, as that many dark pixels along its top edge. Leave the markers out when
+/// comparing the patches.
+pub fn draw_pattern<D: DrawTarget<Color = Gray8>>(
+    target: &mut D,
+    luma: u8,
+) -> Result<(), D::Error> {
+    target.clear(Gray8::new(luma))?;
+    let size = target.bounding_box().size;
+    let corners = (0..size.height)
+        .step_by(PATCH.height as usize)
+        .flat_map(|y| {
+            (0..size.width)
+                .step_by(PATCH.width as usize)
+                .map(move |x| (x, y))
+        });
+    let panel_numbers = (0..size.width.div_ceil(PANEL_WIDTH as u32))
+        .flat_map(|panel| (1..=panel).map(move |i| (panel * PANEL_WIDTH as u32 + i, 0)));
+    target.draw_iter(
+        corners
+            .chain(panel_numbers)
+            .map(|(x, y)| Pixel(Point::new(x as i32, y as i32), Gray8::BLACK)),
+    )
+}
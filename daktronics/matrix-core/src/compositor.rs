@@ -0,0 +1,347 @@
+/// Layered compositing, so that independent pieces of content (e.g. a scrolling ticker and a
+/// clock) can each draw into their own layer without redrawing each other.
+///
+/// Every layer is an offscreen `Gray8` `DrawTarget` the size of the display. Pixels that haven't
+/// been drawn since the layer was last erased are transparent. `Compositor::flatten` blends the
+/// visible layers from the lowest `z` up, over black, and draws the result into a
+/// `DmaFrameBuffer`, which quantizes and dithers it like any other drawing.
+///
+/// The layers double as a shadow copy of the image: each one keeps track of the area that's
+/// changed since the last flatten, ignoring drawing that doesn't change anything, and only that
+/// area is composited again.
+use crate::framebuffer::{DmaFrameBuffer, PANEL_HEIGHT, PANEL_WIDTH};
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// A single layer for a chain of `PANELS` panels
+#[derive(Clone)]
+pub struct Layer<const PANELS: usize = 2> {
+    pixels: [[[u8; PANEL_WIDTH]; PANELS]; PANEL_HEIGHT],
+    /// One bit per pixel, set where the layer has been drawn
+    opaque: [[[u8; PANEL_WIDTH / 8]; PANELS]; PANEL_HEIGHT],
+    z: i8,
+    opacity: u8,
+    brightness: u8,
+    visible: bool,
+    clip: Option<Rectangle>,
+    /// Bounding box of everything that's changed since the last `Compositor::flatten`
+    dirty: Option<Rectangle>,
+}
+
+impl<const PANELS: usize> Layer<PANELS> {
+    /// Width of the layer in pixels, the same as the display's
+    pub const WIDTH: usize = PANELS * PANEL_WIDTH;
+    /// Height of the layer in pixels
+    pub const HEIGHT: usize = PANEL_HEIGHT;
+
+    /// An empty layer at `z` 0, visible at full opacity and brightness, with no clip
+    pub fn new() -> Self {
+        const { assert!(PANEL_WIDTH.is_multiple_of(8)) };
+        Self {
+            pixels: [[[0; PANEL_WIDTH]; PANELS]; PANEL_HEIGHT],
+            opaque: [[[0; PANEL_WIDTH / 8]; PANELS]; PANEL_HEIGHT],
+            z: 0,
+            opacity: u8::MAX,
+            brightness: u8::MAX,
+            visible: true,
+            clip: None,
+            // whatever the framebuffer holds has to be covered the first time
+            dirty: Some(Self::area()),
+        }
+    }
+
+    /// The whole layer
+    const fn area() -> Rectangle {
+        Rectangle::new(
+            Point::zero(),
+            Size::new(Self::WIDTH as u32, Self::HEIGHT as u32),
+        )
+    }
+
+    pub fn z(&self) -> i8 {
+        self.z
+    }
+
+    /// Move the layer in the stack: layers with a higher `z` are drawn on top of ones with a lower
+    /// one. Layers with the same `z` are stacked in the order they're in in the compositor.
+    pub fn set_z(&mut self, z: i8) {
+        if z != self.z {
+            self.z = z;
+            self.mark_dirty(self.shown_area());
+        }
+    }
+
+    pub fn opacity(&self) -> u8 {
+        self.opacity
+    }
+
+    /// How much the layer covers what's below it, from 0 (not at all) to 255 (completely)
+    pub fn set_opacity(&mut self, opacity: u8) {
+        if opacity != self.opacity {
+            self.opacity = opacity;
+            self.mark_dirty(self.shown_area());
+        }
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Scale the layer's pixels by `brightness / 255` when it's composited
+    pub fn set_brightness(&mut self, brightness: u8) {
+        if brightness != self.brightness {
+            self.brightness = brightness;
+            self.mark_dirty(self.shown_area());
+        }
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Hide or show the layer, without touching its contents
+    pub fn set_visible(&mut self, visible: bool) {
+        if visible != self.visible {
+            self.visible = visible;
+            self.mark_dirty(self.shown_area());
+        }
+    }
+
+    pub fn clip(&self) -> Option<Rectangle> {
+        self.clip
+    }
+
+    /// Only show the part of the layer inside `clip`, or all of it for `None`. This doesn't stop
+    /// anything being drawn outside of it, so e.g. a ticker can draw text that runs past its area.
+    pub fn set_clip(&mut self, clip: Option<Rectangle>) {
+        if clip != self.clip {
+            self.mark_dirty(self.shown_area());
+            self.clip = clip;
+            self.mark_dirty(self.shown_area());
+        }
+    }
+
+    /// Bounding box of everything that's changed since the last `Compositor::flatten`, if
+    /// anything has
+    pub fn dirty(&self) -> Option<Rectangle> {
+        self.dirty
+    }
+
+    /// The pixel at `p`, or `None` if it's transparent or off the layer
+    pub fn pixel(&self, p: Point) -> Option<Gray8> {
+        let (x, y) = Self::index(p)?;
+        self.is_opaque(x, y)
+            .then(|| Gray8::new(self.pixels[y][x / PANEL_WIDTH][x % PANEL_WIDTH]))
+    }
+
+    /// Fill the whole layer with `Gray8` pixels, `WIDTH * HEIGHT` bytes in row-major order. Only
+    /// the part of each row that actually changed is marked dirty.
+    pub fn blit_gray8(&mut self, data: &[u8]) {
+        assert_eq!(data.len(), Self::WIDTH * Self::HEIGHT);
+        for (y, line) in data.chunks_exact(Self::WIDTH).enumerate() {
+            let row = self.pixels[y].as_flattened_mut();
+            let opaque = self.opaque[y].as_flattened_mut();
+            let changed = if opaque.iter().any(|&bits| bits != u8::MAX) {
+                opaque.fill(u8::MAX);
+                Some((0, Self::WIDTH - 1))
+            } else {
+                let differs = |(a, b): (&u8, &u8)| a != b;
+                row.iter()
+                    .zip(line)
+                    .position(differs)
+                    .zip(row.iter().zip(line).rposition(differs))
+            };
+            if let Some((first, last)) = changed {
+                row.copy_from_slice(line);
+                let width = (last - first + 1) as u32;
+                self.mark_dirty(Rectangle::new(
+                    Point::new(first as i32, y as i32),
+                    Size::new(width, 1),
+                ));
+            }
+        }
+    }
+
+    /// Make the pixels in `area` transparent again
+    pub fn erase(&mut self, area: &Rectangle) {
+        for p in area.intersection(&Self::area()).points() {
+            let (x, y) = (p.x as usize, p.y as usize);
+            if self.is_opaque(x, y) {
+                self.opaque[y][x / PANEL_WIDTH][x % PANEL_WIDTH / 8] &= !(1 << (x % 8));
+                self.mark_dirty(Rectangle::new(p, Size::new(1, 1)));
+            }
+        }
+    }
+
+    /// Make the whole layer transparent
+    pub fn erase_all(&mut self) {
+        self.erase(&Self::area());
+    }
+
+    /// Where the layer can show up on the display
+    fn shown_area(&self) -> Rectangle {
+        self.clip
+            .map_or(Self::area(), |clip| clip.intersection(&Self::area()))
+    }
+
+    fn mark_dirty(&mut self, area: Rectangle) {
+        if area.is_zero_sized() {
+            return;
+        }
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => union(&dirty, &area),
+            None => area,
+        });
+    }
+
+    fn index(p: Point) -> Option<(usize, usize)> {
+        let (x, y) = (usize::try_from(p.x).ok()?, usize::try_from(p.y).ok()?);
+        (x < Self::WIDTH && y < Self::HEIGHT).then_some((x, y))
+    }
+
+    fn is_opaque(&self, x: usize, y: usize) -> bool {
+        self.opaque[y][x / PANEL_WIDTH][x % PANEL_WIDTH / 8] & (1 << (x % 8)) != 0
+    }
+
+    /// Blend the pixel at (`x`, `y`) over `below`
+    fn blend(&self, x: usize, y: usize, below: u8) -> u8 {
+        if !self.visible
+            || !self.is_opaque(x, y)
+            || self
+                .clip
+                .is_some_and(|clip| !clip.contains(Point::new(x as i32, y as i32)))
+        {
+            return below;
+        }
+        let value = scale(
+            self.pixels[y][x / PANEL_WIDTH][x % PANEL_WIDTH],
+            self.brightness,
+        );
+        let opacity = self.opacity as u32;
+        ((value as u32 * opacity + below as u32 * (255 - opacity) + 127) / 255) as u8
+    }
+}
+
+/// The smallest rectangle that covers both `a` and `b`, neither of which can be empty
+fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    let (a_end, b_end) = (a.bottom_right().unwrap(), b.bottom_right().unwrap());
+    Rectangle::with_corners(
+        a.top_left.component_min(b.top_left),
+        a_end.component_max(b_end),
+    )
+}
+
+/// `value * factor / 255`, rounded
+fn scale(value: u8, factor: u8) -> u8 {
+    ((value as u32 * factor as u32 + 127) / 255) as u8
+}
+
+impl<const PANELS: usize> Default for Layer<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PANELS: usize> OriginDimensions for Layer<PANELS> {
+    fn size(&self) -> Size {
+        Size::new(Self::WIDTH as u32, Self::HEIGHT as u32)
+    }
+}
+
+/// Drawing makes the pixels opaque, including drawing black, and `DrawTarget::clear` fills the
+/// whole layer. Use `Layer::erase` to make pixels transparent.
+impl<const PANELS: usize> DrawTarget for Layer<PANELS> {
+    type Color = Gray8;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(p, color) in pixels {
+            let Some((x, y)) = Self::index(p) else {
+                continue;
+            };
+            let opaque = self.is_opaque(x, y);
+            let pixel = &mut self.pixels[y][x / PANEL_WIDTH][x % PANEL_WIDTH];
+            if *pixel == color.luma() && opaque {
+                continue;
+            }
+            *pixel = color.luma();
+            self.opaque[y][x / PANEL_WIDTH][x % PANEL_WIDTH / 8] |= 1 << (x % 8);
+            self.mark_dirty(Rectangle::new(p, Size::new(1, 1)));
+        }
+        Ok(())
+    }
+}
+
+/// A stack of `LAYERS` layers for a chain of `PANELS` panels
+pub struct Compositor<const LAYERS: usize, const PANELS: usize = 2> {
+    layers: [Layer<PANELS>; LAYERS],
+    /// What the last `flatten` changed, which the other buffer of a `DoubleBuffer` is missing
+    flattened: Option<Rectangle>,
+}
+
+impl<const LAYERS: usize, const PANELS: usize> Compositor<LAYERS, PANELS> {
+    /// A compositor with every layer transparent, at `z` 0
+    pub fn new() -> Self {
+        Self {
+            layers: core::array::from_fn(|_| Layer::new()),
+            flattened: None,
+        }
+    }
+
+    pub fn layer(&self, layer: usize) -> &Layer<PANELS> {
+        &self.layers[layer]
+    }
+
+    pub fn layer_mut(&mut self, layer: usize) -> &mut Layer<PANELS> {
+        &mut self.layers[layer]
+    }
+
+    /// Blend all of the visible layers over black and draw the result into `fb`.
+    ///
+    /// Only the area that's changed over the last two flattens is drawn, so `fb` has to hold one of
+    /// the last two frames this flattened, as the back buffer of a `DoubleBuffer` does. Call
+    /// `invalidate` if anything else draws into it. Error diffusion starts over at the edges of the
+    /// area, so it can leave faint seams until the next full flatten.
+    pub fn flatten(&mut self, fb: &mut DmaFrameBuffer<PANELS>) {
+        let dirty = self
+            .layers
+            .iter_mut()
+            .filter_map(|layer| layer.dirty.take())
+            .reduce(|a, b| union(&a, &b));
+        let flattened = core::mem::replace(&mut self.flattened, dirty);
+        let Some(area) = dirty
+            .into_iter()
+            .chain(flattened)
+            .reduce(|a, b| union(&a, &b))
+        else {
+            return;
+        };
+        let mut order: [usize; LAYERS] = core::array::from_fn(|i| i);
+        order.sort_unstable_by_key(|&i| (self.layers[i].z, i));
+        let pixels = area.points().map(|p| {
+            let (x, y) = (p.x as usize, p.y as usize);
+            let luma = order
+                .iter()
+                .fold(0, |below, &i| self.layers[i].blend(x, y, below));
+            Gray8::new(luma)
+        });
+        fb.fill_contiguous(&area, pixels).unwrap();
+    }
+
+    /// Composite the whole display on the next `flatten`
+    pub fn invalidate(&mut self) {
+        for layer in &mut self.layers {
+            layer.dirty = Some(Layer::<PANELS>::area());
+        }
+    }
+}
+
+impl<const LAYERS: usize, const PANELS: usize> Default for Compositor<LAYERS, PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,81 @@
+use core::str::FromStr;
+use embedded_storage::Storage;
+use heapless::String;
+
+pub const CONFIG_ENTRY_LEN: usize = 64;
+
+pub const SSID_STORE_ID: u32 = 0;
+pub const PW_STORE_ID: u32 = 1;
+/// See `framebuffer::Orientation` for the format
+pub const ORIENTATION_STORE_ID: u32 = 2;
+/// See `timing::DisplayTiming` for the format
+pub const TIMING_STORE_ID: u32 = 3;
+/// See `dither::Dithering` for the format
+pub const DITHERING_STORE_ID: u32 = 4;
+/// See `power::PowerConfig` for the format
+pub const POWER_STORE_ID: u32 = 5;
+/// See `gamma::GammaLut` for the format
+pub const GAMMA_STORE_ID: u32 = 6;
+
+#[derive(Debug)]
+pub enum ConfigError<E> {
+    /// The value doesn't fit in a config entry
+    TooLong,
+    /// The stored value isn't valid UTF-8, e.g. because it was never set
+    Invalid,
+    Storage(E),
+}
+
+/// Stores config values as fixed-size, zero-padded strings, one entry per ID, starting at
+/// `base_address`
+pub struct ConfigStore<S> {
+    storage: S,
+    base_address: u32,
+}
+
+impl<S: Storage> ConfigStore<S> {
+    pub fn new(storage: S, base_address: u32) -> Self {
+        Self {
+            storage,
+            base_address,
+        }
+    }
+
+    fn offset_of_id(&self, id: u32) -> u32 {
+        self.base_address + (id * CONFIG_ENTRY_LEN as u32)
+    }
+
+    pub fn set(&mut self, id: u32, value: &str) -> Result<(), ConfigError<S::Error>> {
+        let buf = encode_entry(value).ok_or(ConfigError::TooLong)?;
+        let offset = self.offset_of_id(id);
+        self.storage
+            .write(offset, buf.as_slice())
+            .map_err(ConfigError::Storage)
+    }
+
+    pub fn get(&mut self, id: u32) -> Result<String<CONFIG_ENTRY_LEN>, ConfigError<S::Error>> {
+        let mut buf = [0; CONFIG_ENTRY_LEN];
+        let offset = self.offset_of_id(id);
+        self.storage
+            .read(offset, &mut buf)
+            .map_err(ConfigError::Storage)?;
+        decode_entry(&buf).ok_or(ConfigError::Invalid)
+    }
+}
+
+/// Serialize a config value into an entry
+pub fn encode_entry(value: &str) -> Option<[u8; CONFIG_ENTRY_LEN]> {
+    if value.len() > CONFIG_ENTRY_LEN {
+        return None;
+    }
+
+    let mut buf = [0; CONFIG_ENTRY_LEN];
+    buf[..value.len()].copy_from_slice(value.as_bytes());
+    Some(buf)
+}
+
+/// Deserialize a config value from an entry
+pub fn decode_entry(buf: &[u8; CONFIG_ENTRY_LEN]) -> Option<String<CONFIG_ENTRY_LEN>> {
+    let s = core::str::from_utf8(buf).ok()?;
+    String::from_str(s.trim_matches(char::from(0))).ok()
+}
@@ -0,0 +1,108 @@
+/// Ambient light based dimming: maps light sensor readings to a global display brightness, with
+/// smoothing and hysteresis so that the display doesn't flicker between levels.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Global display brightness, shared between the dimming task and the matrix driver
+pub struct Brightness(AtomicU8);
+
+impl Brightness {
+    pub const fn new(level: u8) -> Self {
+        Self(AtomicU8::new(level))
+    }
+
+    pub fn get(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, level: u8) {
+        self.0.store(level, Ordering::Relaxed);
+    }
+}
+
+/// Something that can measure the ambient light level
+pub trait LightSensor {
+    /// Read the current light level, from 0 (darkest) to `u16::MAX` (brightest)
+    fn read(&mut self) -> u16;
+}
+
+pub struct DimmingConfig {
+    /// Light level at (and below) which the display is at `min_brightness`
+    pub dark: u16,
+    /// Light level at (and above) which the display is at `max_brightness`
+    pub bright: u16,
+    pub min_brightness: u8,
+    pub max_brightness: u8,
+    /// How far the brightness has to move before it's actually changed, so that it doesn't
+    /// flicker back and forth when the light level is close to a step
+    pub hysteresis: u8,
+    /// How quickly the smoothed light level follows the readings, as a shift: each reading moves
+    /// it `1 / 2^smoothing` of the way
+    pub smoothing: u8,
+}
+
+impl Default for DimmingConfig {
+    fn default() -> Self {
+        Self {
+            dark: 0,
+            bright: u16::MAX,
+            min_brightness: 16,
+            max_brightness: u8::MAX,
+            hysteresis: 8,
+            smoothing: 3,
+        }
+    }
+}
+
+impl DimmingConfig {
+    /// Target brightness for a (smoothed) light level
+    fn brightness_for(&self, level: u16) -> u8 {
+        let (dark, bright) = (self.dark as i32, self.bright as i32);
+        let (min, max) = (self.min_brightness as i32, self.max_brightness as i32);
+        if bright == dark {
+            return if level as i32 >= bright {
+                max as u8
+            } else {
+                min as u8
+            };
+        }
+        let t = ((level as i32 - dark) * 256 / (bright - dark)).clamp(0, 256);
+        (min + (max - min) * t / 256) as u8
+    }
+}
+
+/// Turns a stream of light level readings into a brightness
+pub struct Dimmer {
+    config: DimmingConfig,
+    smoothed: Option<i32>,
+    current: u8,
+}
+
+impl Dimmer {
+    pub fn new(config: DimmingConfig) -> Self {
+        let current = config.max_brightness;
+        Self {
+            config,
+            smoothed: None,
+            current,
+        }
+    }
+
+    /// Feed in a new light level reading, returning the brightness to use
+    pub fn update(&mut self, level: u16) -> u8 {
+        let Some(smoothed) = self.smoothed.as_mut() else {
+            // go straight to the right brightness at startup
+            self.smoothed = Some(level as i32);
+            self.current = self.config.brightness_for(level);
+            return self.current;
+        };
+        *smoothed += (level as i32 - *smoothed) >> self.config.smoothing;
+
+        let target = self.config.brightness_for(*smoothed as u16);
+        // always let it reach the ends of the range, even if that's a smaller step
+        let at_limit = target == self.config.min_brightness || target == self.config.max_brightness;
+        if target.abs_diff(self.current) >= self.config.hysteresis || at_limit {
+            self.current = target;
+        }
+        self.current
+    }
+}
@@ -0,0 +1,208 @@
+/// Dithering, to hide the banding from quantizing `Gray8` content down to `BITS` bits.
+///
+/// Spatial dithering trades resolution for gray levels: ordered dithering adds a Bayer threshold
+/// pattern before rounding, and error diffusion (Floyd-Steinberg) pushes each pixel's rounding
+/// error onto its neighbours. Temporal dithering quantizes to `TEMPORAL_BITS` more bits than the
+/// bit planes can hold, and shows the extra bits by alternating between the two nearest levels
+/// over consecutive refresh cycles (see `DmaFrameBuffer::advance_temporal_dither`).
+use crate::calibration::apply_gain;
+use crate::framebuffer::{BITS, MAX_PANELS, PANEL_WIDTH};
+use crate::gamma::GammaLut;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+
+/// Extra bits of gray shown by temporal dithering, which takes `1 << TEMPORAL_BITS` refresh cycles
+/// to cycle through
+pub const TEMPORAL_BITS: u8 = 2;
+
+/// 4×4 Bayer matrix
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+/// Order in which a 2×2 block of pixels steps through the temporal levels, so that neighbouring
+/// pixels don't all change on the same refresh cycle
+const TEMPORAL_ORDER: [[u8; 2]; 2] = [[0, 2], [3, 1]];
+
+/// How pixels are dithered spatially
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SpatialDither {
+    /// Round to the nearest level
+    #[default]
+    None,
+    /// Bayer ordered dithering, which is stable from frame to frame, so it suits text and
+    /// animations
+    Ordered,
+    /// Floyd-Steinberg error diffusion, which looks best on photos and video. It needs the pixels
+    /// in order, so it only applies to the bulk paths (`blit_gray8`, `fill_contiguous` and
+    /// `fill_solid`); pixels drawn one at a time are ordered dithered instead.
+    Diffusion,
+}
+
+/// How `DmaFrameBuffer` quantizes `Gray8` content to its bit planes.
+///
+/// Stored as the spatial dithering (`none`, `ordered` or `diffusion`), followed by `+temporal` if
+/// temporal dithering is on, e.g. `ordered+temporal`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Dithering {
+    pub spatial: SpatialDither,
+    pub temporal: bool,
+}
+
+impl Dithering {
+    /// Number of steps from black to white before the temporal bits are split off
+    fn steps(&self) -> u16 {
+        let max = (1 << BITS) - 1;
+        if self.temporal {
+            max << TEMPORAL_BITS
+        } else {
+            max
+        }
+    }
+
+    /// `gamma` scaled to 1/256ths of a step, for quantizing without a division per pixel
+    pub(crate) fn scaled_levels(&self, gamma: &GammaLut) -> [u16; 256] {
+        let steps = self.steps() as u32 * 256;
+        core::array::from_fn(|luma| {
+            let intensity = gamma.intensity(luma as u8) as u32;
+            ((intensity * steps + u16::MAX as u32 / 2) / u16::MAX as u32) as u16
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseDitheringError;
+
+impl FromStr for Dithering {
+    type Err = ParseDitheringError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spatial, temporal) = match s.strip_suffix("+temporal") {
+            Some(spatial) => (spatial, true),
+            None => (s, false),
+        };
+        let spatial = match spatial {
+            "none" => SpatialDither::None,
+            "ordered" => SpatialDither::Ordered,
+            "diffusion" => SpatialDither::Diffusion,
+            _ => return Err(ParseDitheringError),
+        };
+        Ok(Self { spatial, temporal })
+    }
+}
+
+impl Display for Dithering {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let spatial = match self.spatial {
+            SpatialDither::None => "none",
+            SpatialDither::Ordered => "ordered",
+            SpatialDither::Diffusion => "diffusion",
+        };
+        write!(f, "{spatial}")?;
+        if self.temporal {
+            write!(f, "+temporal")?;
+        }
+        Ok(())
+    }
+}
+
+/// The temporal bits to add to a pixel's level at `phase`, for the pixel at scan row `row` and
+/// position `x` of the chain
+pub(crate) fn temporal_offset(phase: u8, row: usize, x: usize) -> u8 {
+    (phase + TEMPORAL_ORDER[row & 1][x & 1]) & ((1 << TEMPORAL_BITS) - 1)
+}
+
+/// What to add to a pixel's value before rounding it down, in 1/256ths of a step
+fn threshold(spatial: SpatialDither, x: usize, y: usize) -> u16 {
+    match spatial {
+        SpatialDither::Ordered => BAYER[y % 4][x % 4] as u16 * 16 + 8,
+        SpatialDither::None | SpatialDither::Diffusion => 128,
+    }
+}
+
+/// A level in steps of `dithering`, including the temporal bits
+fn fine_level(dithering: Dithering, level: u16) -> u8 {
+    if dithering.temporal {
+        level as u8
+    } else {
+        (level as u8) << TEMPORAL_BITS
+    }
+}
+
+/// Quantize a single pixel on its own, for when pixels aren't drawn in order, so error diffusion
+/// falls back to ordered dithering. `levels` comes from `Dithering::scaled_levels`, and `gain` is
+/// the pixel's uniformity calibration.
+pub(crate) fn quantize_pixel(
+    dithering: Dithering,
+    levels: &[u16; 256],
+    x: usize,
+    y: usize,
+    luma: u8,
+    gain: u8,
+) -> u8 {
+    let spatial = match dithering.spatial {
+        SpatialDither::Diffusion => SpatialDither::Ordered,
+        spatial => spatial,
+    };
+    let value = apply_gain(levels[luma as usize], gain);
+    fine_level(dithering, (value + threshold(spatial, x, y)) >> 8)
+}
+
+/// Widest area that error diffusion has to keep errors for
+const MAX_WIDTH: usize = MAX_PANELS * PANEL_WIDTH;
+
+/// Quantizes the pixels of a single drawing operation, which have to come in row-major order if
+/// they're error diffused. The levels include the temporal bits even if temporal dithering is off.
+pub(crate) struct Quantizer<'a> {
+    dithering: Dithering,
+    levels: &'a [u16; 256],
+    /// Left edge of the area being drawn
+    left: usize,
+    /// The row that `errors[0]` is for
+    y: usize,
+    /// Rounding errors pushed onto the current and the next row, in 1/256ths of a step, offset by
+    /// one pixel so that there's room on either side
+    errors: [[i16; MAX_WIDTH + 2]; 2],
+}
+
+impl<'a> Quantizer<'a> {
+    /// Quantize the pixels of an area starting at (`left`, `top`)
+    pub fn new(dithering: Dithering, levels: &'a [u16; 256], left: usize, top: usize) -> Self {
+        Self {
+            dithering,
+            levels,
+            left,
+            y: top,
+            errors: [[0; MAX_WIDTH + 2]; 2],
+        }
+    }
+
+    /// Quantize the pixel at (`x`, `y`), with uniformity calibration gain `gain`
+    pub fn quantize(&mut self, x: usize, y: usize, luma: u8, gain: u8) -> u8 {
+        let value = apply_gain(self.levels[luma as usize], gain);
+        let level = match self.dithering.spatial {
+            SpatialDither::Diffusion => self.diffuse(x, y, value),
+            spatial => (value + threshold(spatial, x, y)) >> 8,
+        };
+        fine_level(self.dithering, level)
+    }
+
+    fn diffuse(&mut self, x: usize, y: usize, value: u16) -> u16 {
+        while self.y < y {
+            self.errors[0] = self.errors[1];
+            self.errors[1] = [0; MAX_WIDTH + 2];
+            self.y += 1;
+        }
+        let i = x - self.left + 1;
+        let max = self.dithering.steps() as i32 * 256;
+        let wanted = (value as i32 + self.errors[0][i] as i32).clamp(0, max);
+        let level = ((wanted + 128) >> 8).min(max >> 8);
+        let error = wanted - (level << 8);
+        // the weights are out of 16
+        let mut push = |row: usize, i: usize, weight: i32| {
+            self.errors[row][i] = self.errors[row][i].saturating_add((error * weight / 16) as i16);
+        };
+        push(0, i + 1, 7);
+        push(1, i - 1, 3);
+        push(1, i, 5);
+        push(1, i + 1, 1);
+        level as u16
+    }
+}
@@ -0,0 +1,60 @@
+use crate::framebuffer::DmaFrameBuffer;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::{MappedMutexGuard, Mutex, MutexGuard};
+
+/// A pair of framebuffers, so that the matrix driver always renders a complete frame.
+///
+/// Producers draw into the back buffer and call [`DoubleBuffer::present`] once they're done. The
+/// driver owns the front buffer, and calls [`DoubleBuffer::swap`] at the end of every refresh cycle
+/// to pick up the presented frame. The buffers themselves are handed back and forth through
+/// channels, so each one is only ever accessed by one side at a time.
+pub struct DoubleBuffer<const PANELS: usize = 2> {
+    back: Mutex<CriticalSectionRawMutex, Option<&'static mut DmaFrameBuffer<PANELS>>>,
+    presented: Channel<CriticalSectionRawMutex, &'static mut DmaFrameBuffer<PANELS>, 1>,
+    released: Channel<CriticalSectionRawMutex, &'static mut DmaFrameBuffer<PANELS>, 1>,
+}
+
+impl<const PANELS: usize> DoubleBuffer<PANELS> {
+    /// Create a double buffer that producers will draw into `back` first. The front buffer is kept
+    /// by the driver and passed to [`DoubleBuffer::swap`].
+    pub fn new(back: &'static mut DmaFrameBuffer<PANELS>) -> Self {
+        Self {
+            back: Mutex::new(Some(back)),
+            presented: Channel::new(),
+            released: Channel::new(),
+        }
+    }
+
+    /// Lock the back buffer for drawing
+    pub async fn back(
+        &self,
+    ) -> MappedMutexGuard<'_, CriticalSectionRawMutex, DmaFrameBuffer<PANELS>> {
+        MutexGuard::map(self.back.lock().await, |back| {
+            // only ever empty while `present` holds the lock
+            &mut **back.as_mut().unwrap()
+        })
+    }
+
+    /// Hand the back buffer over to the driver, waiting until it's shown at the end of the current
+    /// refresh cycle. The new back buffer is the one that was shown before, so it's a frame behind:
+    /// producers have to draw what changed in the last two frames, which `Compositor::flatten`
+    /// does.
+    pub async fn present(&self) {
+        let mut back = self.back.lock().await;
+        self.presented.send(back.take().unwrap()).await;
+        *back = Some(self.released.receive().await);
+    }
+
+    /// Called by the driver at the end of every refresh cycle to swap in a presented frame, if
+    /// there is one. Returns whether there was.
+    pub fn swap(&self, front: &mut &'static mut DmaFrameBuffer<PANELS>) -> bool {
+        let Ok(presented) = self.presented.try_receive() else {
+            return false;
+        };
+        let old = core::mem::replace(front, presented);
+        // `present` is waiting for this, and nobody else can send on this channel
+        self.released.try_send(old).ok().unwrap();
+        true
+    }
+}
@@ -0,0 +1,266 @@
+/// The byte stream that gets clocked out to the matrix, one byte per clock cycle (see `Entry`), so
+/// that a driver can send it with DMA without any processing.
+///
+/// Every byte carries the row address, LE/MOD and blanking along with a single pixel bit, and
+/// every clock shifts in a pixel, so the rows that shift in pixel data take a byte per bit of it.
+/// What's kept down is everything else:
+/// - rows that only display, without shifting anything in. Each bit plane's bottom row is
+///   displayed while the next plane's top row is shifted in, so there's only one of these per
+///   refresh (see `Frame`), instead of one per bit plane.
+/// - the repeats of the upper bit planes, which are extra descriptors pointing at the same data
+///   (see `transfers`)
+/// - the encoding itself, between framebuffers. It's only needed by the drivers that clock it out
+///   as is (PARL_IO and LCD_CAM), and one `DmaEncoding` is enough for them however many
+///   framebuffers there are: `update` encodes whichever one is about to be shown, and only
+///   rewrites the rows whose pixel data changed, and the control signals of the bit planes whose
+///   on-time changed. The pixel data is compared against what's already encoded, so there's no
+///   separate copy of it.
+use crate::framebuffer::{
+    plane_repeats, DmaFrameBuffer, BITS, FULL_PLANE, MAX_PANELS, PANEL_CHAIN, REFRESH_TRANSFERS,
+    ROWS,
+};
+use bitfield::bitfield;
+
+bitfield! {
+    /// An 8-bit word representing the control signals for a single pixel/clock cycle
+    #[derive(Clone, Copy, Default, PartialEq)]
+    #[repr(transparent)]
+    pub(crate) struct Entry(u8);
+    impl Debug;
+    unused1, set_unused1: 7;
+    /// CD OE/SW/ED, which isn't necessarily wired: the display is blanked through the row decoder
+    /// instead, so this is only used to switch the column drivers into error detection mode
+    pub(crate) output_disable, set_output_disable: 6;
+    pub(crate) value, set_value: 5;
+    pub(crate) le_mod, set_le_mod: 4;
+    pub(crate) output_blank, set_output_blank: 3;
+    pub(crate) row, set_row: 2, 0;
+}
+
+/// The value the pins should be held at in between transfers: no clock, and the display blanked
+pub const IDLE_VALUE: u8 = 1 << 3;
+
+const ROW_EXTRA: usize = 1;
+
+/// Represents a single row of pixels in the framebuffer.
+///
+/// This struct manages the control signals for the matrix (row, latch, etc) and sets them
+/// appropriately.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+struct Row<const PANELS: usize> {
+    data: [[Entry; PANEL_CHAIN]; PANELS],
+    extra: [Entry; ROW_EXTRA],
+}
+
+impl<const PANELS: usize> Row<PANELS> {
+    /// Number of bits in the whole shift register chain
+    const COLS: usize = PANELS * PANEL_CHAIN;
+
+    /// Shift in the data for row `addr` while showing the previously latched row `prev_addr` for
+    /// `on_time` clock cycles
+    pub fn format(&mut self, addr: u8, prev_addr: u8, on_time: usize, latch_width: usize) {
+        let mut entry = Entry(0);
+        entry.set_row(prev_addr);
+        self.data.as_flattened_mut().fill(entry);
+        self.set_timing(on_time, latch_width);
+
+        entry.set_row(addr);
+        entry.set_output_blank(true);
+        self.extra.fill(entry);
+    }
+
+    /// Change how long the previously latched row is shown for, and how long LE/MOD is held high
+    /// for at the end of the row, without touching the pixel data
+    pub fn set_timing(&mut self, on_time: usize, latch_width: usize) {
+        let latch_start = Self::COLS - latch_width.clamp(1, Self::COLS);
+        for (x, data) in self.data.as_flattened_mut().iter_mut().enumerate() {
+            // if we enable display too soon then we will have ghosting
+            data.set_output_blank(!(1..1 + on_time).contains(&x));
+            data.set_le_mod(x >= latch_start);
+        }
+    }
+
+    /// Whether the pixel data is already `data`, packed the way `DmaFrameBuffer::row_data` has it
+    pub fn has_data(&self, data: &[u8]) -> bool {
+        self.data
+            .as_flattened()
+            .chunks_exact(8)
+            .zip(data)
+            .all(|(entries, &byte)| {
+                entries
+                    .iter()
+                    .enumerate()
+                    .all(|(bit, entry)| entry.value() == (byte & (0x80 >> bit) != 0))
+            })
+    }
+
+    /// Change the pixel data, packed the way `DmaFrameBuffer::row_data` has it, without touching
+    /// the control signals
+    pub fn set_data(&mut self, data: &[u8]) {
+        for (entries, &byte) in self.data.as_flattened_mut().chunks_exact_mut(8).zip(data) {
+            for (bit, entry) in entries.iter_mut().enumerate() {
+                entry.set_value(byte & (0x80 >> bit) != 0);
+            }
+        }
+    }
+}
+
+/// A single bit plane.
+///
+/// Each row is displayed while the next one is shifted in, so the top row displays the bottom row
+/// of the plane sent before it, for that plane's on-time. The planes of a refresh are sent back to
+/// back, and the bottom row of the last one is displayed by the refresh's tail. The first plane
+/// keeps the display blanked instead, since what's latched when a refresh starts (e.g. after the
+/// driver was restarted) could be anything.
+///
+/// Repeats of a plane display the bottom row of the repeat before them, which works because only
+/// the planes above `FULL_PLANE` are repeated, and they have the same on-time as the plane before
+/// them.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct Frame<const PANELS: usize> {
+    rows: [Row<PANELS>; ROWS],
+}
+
+const _: () = assert!(
+    plane_repeats(FULL_PLANE) == 1,
+    "repeated planes need a full plane before them"
+);
+
+impl<const PANELS: usize> Frame<PANELS> {
+    /// `prev_on_time` is the on-time of the plane sent before this one, which its bottom row is
+    /// displayed for
+    pub fn format(&mut self, prev_on_time: usize, on_time: usize, latch_width: usize) {
+        for (addr, row) in self.rows.iter_mut().enumerate() {
+            let prev_addr = addr.checked_sub(1).unwrap_or(ROWS - 1);
+            row.format(addr as u8, prev_addr as u8, 0, latch_width);
+        }
+        self.set_timing(prev_on_time, on_time, latch_width);
+    }
+
+    pub fn set_timing(&mut self, prev_on_time: usize, on_time: usize, latch_width: usize) {
+        for (addr, row) in self.rows.iter_mut().enumerate() {
+            row.set_timing(if addr == 0 { prev_on_time } else { on_time }, latch_width);
+        }
+    }
+}
+
+/// Longest single transfer that the driver has to send, i.e. one bit plane, for allocating DMA
+/// descriptors
+pub const MAX_TRANSFER_LEN: usize = size_of::<Frame<MAX_PANELS>>();
+
+/// A framebuffer for a chain of `PANELS` panels, encoded for DMA
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct DmaEncoding<const PANELS: usize = 2> {
+    _align: u64,
+    frames: [Frame<PANELS>; BITS as usize],
+    /// Displays the bottom row of the last plane, without shifting anything in, then blanks the
+    /// display. This has to follow `frames` directly, for `as_bytes`.
+    tail: Row<PANELS>,
+    /// The on-time of every bit plane that `frames` was last encoded with
+    on_times: [usize; BITS as usize],
+    latch_width: usize,
+}
+
+impl<const PANELS: usize> DmaEncoding<PANELS> {
+    /// Length of the longest single item of `transfers`, i.e. one bit plane
+    pub const TRANSFER_LEN: usize = size_of::<Frame<PANELS>>();
+    /// Length of one full refresh: every repeat of every bit plane, and the tail
+    pub const REFRESH_LEN: usize =
+        REFRESH_TRANSFERS * Self::TRANSFER_LEN + size_of::<Row<PANELS>>();
+
+    /// An encoding that keeps the display blank until the first `update`
+    pub fn new() -> Self {
+        let latch_width = 1;
+        let mut row = Row {
+            data: [[Entry(0); _]; _],
+            extra: [Entry(0); _],
+        };
+        let mut frame = Frame { rows: [row; _] };
+        frame.format(0, 0, latch_width);
+        row.format(ROWS as u8 - 1, ROWS as u8 - 1, 0, latch_width);
+        Self {
+            _align: 0,
+            frames: [frame; _],
+            tail: row,
+            on_times: [0; _],
+            latch_width,
+        }
+    }
+
+    /// Encode `fb`, rewriting whatever changed since the last `update`
+    pub fn update(&mut self, fb: &DmaFrameBuffer<PANELS>) {
+        let latch_width = fb.timing().latch_width;
+        let retime = latch_width != self.latch_width;
+        let on_times: [usize; BITS as usize] = core::array::from_fn(|plane| fb.on_time(plane));
+        // the first plane doesn't display anything from before it
+        let mut prev = (0, 0);
+        for (plane, frame) in self.frames.iter_mut().enumerate() {
+            let on_time = (on_times[plane], self.on_times[plane]);
+            if retime || on_time.0 != on_time.1 || prev.0 != prev.1 {
+                frame.set_timing(prev.0, on_time.0, latch_width);
+            }
+            for (row, data) in fb.bit_planes()[plane].iter().enumerate() {
+                let data = data.as_flattened();
+                if !frame.rows[row].has_data(data) {
+                    frame.rows[row].set_data(data);
+                }
+            }
+            prev = on_time;
+        }
+        if retime || prev.0 != prev.1 {
+            self.tail.set_timing(prev.0, latch_width);
+        }
+        self.on_times = on_times;
+        self.latch_width = latch_width;
+    }
+
+    /// The raw DMA data for a single bit plane
+    fn plane_buffer(&self, plane: usize) -> &[u8] {
+        let frame = &self.frames[plane];
+        unsafe { core::slice::from_raw_parts(frame as *const _ as *const u8, size_of_val(frame)) }
+    }
+
+    /// The data for one full refresh of the display, in the order that it needs to be sent: each
+    /// bit plane, repeated as many times as its weight requires, then the tail. The items have to
+    /// be sent back to back, since each one displays the end of the one before it, but refreshes
+    /// can be separate transfers with the pins held at `IDLE_VALUE` in between, since every
+    /// refresh starts and ends with the display blanked.
+    pub fn transfers(&self) -> impl Iterator<Item = &[u8]> {
+        let tail = unsafe {
+            core::slice::from_raw_parts(
+                &self.tail as *const _ as *const u8,
+                size_of_val(&self.tail),
+            )
+        };
+        (0..BITS as usize)
+            .flat_map(move |plane| {
+                core::iter::repeat_n(self.plane_buffer(plane), plane_repeats(plane))
+            })
+            .chain([tail])
+    }
+
+    /// All of the bit planes in order, followed by the tail
+    pub fn as_bytes(&self) -> &[u8] {
+        let frames = &self.frames;
+        // nothing in here needs aligning, so the tail comes straight after the frames
+        let len = size_of_val(frames) + size_of_val(&self.tail);
+        unsafe { core::slice::from_raw_parts(frames as *const _ as *const u8, len) }
+    }
+}
+
+impl<const PANELS: usize> Default for DmaEncoding<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PANELS: usize> From<&DmaFrameBuffer<PANELS>> for DmaEncoding<PANELS> {
+    fn from(fb: &DmaFrameBuffer<PANELS>) -> Self {
+        let mut encoding = Self::new();
+        encoding.update(fb);
+        encoding
+    }
+}
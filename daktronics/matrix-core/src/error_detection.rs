@@ -0,0 +1,231 @@
+/// Open/short LED detection, using the error detection mode of the MBI5169 column drivers.
+///
+/// The drivers are switched into error detection mode with a sequence on CD LE/MOD and
+/// CD OE/SW/ED (see "Operation Mode Switching" in the MBI5169 datasheet). Pulling OE/SW/ED low for
+/// at least 2 µs in that mode makes every output compare its voltage against a threshold, and a
+/// clock edge while it's still low loads the results into the shift register: 1 if the output is
+/// fine, 0 if not. They're then read back through the SDO of the last driver in the chain, one bit
+/// per clock, with OE/SW/ED high so that the clock only shifts.
+///
+/// Every output is switched on for the test, and each scan row is tested twice:
+/// - with the row selected, where a healthy LED conducts, so an output that doesn't is open
+/// - with no row selected, where nothing should conduct, so an output that does is shorted
+///
+/// This needs OE/SW/ED driven by the controller (it's normally left enabled, since the display is
+/// blanked through the row decoder), and SDO of the last panel wired back to an input.
+use crate::encoding::Entry;
+use crate::framebuffer::{DmaFrameBuffer, PANEL_CHAIN, PANEL_HEIGHT, PANEL_WIDTH, ROWS};
+use core::fmt::Write;
+
+/// Number of clock cycles that OE/SW/ED is held low for. The clock runs at 1 MHz, so this is
+/// comfortably over the 2 µs that the drivers need.
+const DETECT_LEN: usize = 4;
+
+/// Clock cycles in a mode switching sequence
+const MODE_SWITCH_LEN: usize = 5;
+
+/// Number of scan rows to test
+pub const SCAN_ROWS: usize = ROWS;
+
+/// Which kind of fault a test pass looks for
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Test {
+    Open,
+    Short,
+}
+
+impl Test {
+    pub const ALL: [Self; 2] = [Self::Open, Self::Short];
+}
+
+/// Result of testing a single LED
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Fault {
+    #[default]
+    None,
+    Open,
+    Short,
+}
+
+/// Data for testing a single scan row, in the order it's sent
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Setup<const PANELS: usize> {
+    /// All ones, latched into the outputs while still in normal mode
+    load: [[Entry; PANEL_CHAIN]; PANELS],
+    to_error_detection: [Entry; MODE_SWITCH_LEN],
+    detect: [Entry; DETECT_LEN],
+}
+
+/// The sequences of bytes to send to the matrix to run error detection, one scan row and test at a
+/// time:
+/// 1. send `setup` for the row and test
+/// 2. read SDO, then send `shift`, once for every bit in the chain (`ErrorDetection::CHAIN_LEN`)
+/// 3. send `finish`
+///
+/// Each of these should be sent as a separate transfer, with the pins held at `IDLE_VALUE` in
+/// between, which stops the clock so that SDO can be read.
+pub struct ErrorDetection<const PANELS: usize = 2> {
+    setup: Setup<PANELS>,
+    shift: [Entry; 1],
+    finish: [Entry; MODE_SWITCH_LEN],
+}
+
+impl<const PANELS: usize> ErrorDetection<PANELS> {
+    /// Number of status bits to read back per scan row
+    pub const CHAIN_LEN: usize = PANELS * PANEL_CHAIN;
+
+    pub fn new() -> Self {
+        let mut blank = Entry(0);
+        blank.set_output_blank(true);
+
+        let mut one = blank;
+        one.set_value(true);
+        let mut setup = Setup {
+            load: [[one; _]; _],
+            to_error_detection: Self::mode_switch(blank, true),
+            detect: [blank; _],
+        };
+        // the latch is transparent, so latch on the last bit like `Row::format` does
+        setup.load[PANELS - 1][PANEL_CHAIN - 1].set_le_mod(true);
+
+        let mut shift = blank;
+        shift.set_output_disable(true);
+        Self {
+            setup,
+            shift: [shift],
+            finish: Self::mode_switch(blank, false),
+        }
+    }
+
+    /// The clock cycles that switch the drivers into error detection mode, or back to normal mode.
+    /// OE/SW/ED pulses low on the second cycle, and LE/MOD is high on the fourth to go into error
+    /// detection mode or low to go back.
+    fn mode_switch(base: Entry, error_detection: bool) -> [Entry; MODE_SWITCH_LEN] {
+        let mut entries = [base; MODE_SWITCH_LEN];
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.set_output_disable(i != 1);
+            entry.set_le_mod(error_detection && i == 3);
+        }
+        entries
+    }
+
+    /// Load the test pattern and run `test` on scan row `row`, leaving the results in the shift
+    /// registers
+    pub fn setup(&mut self, row: usize, test: Test) -> &[u8] {
+        for entry in self.setup.detect.iter_mut() {
+            entry.set_row(row as u8);
+            entry.set_output_blank(test == Test::Short);
+            entry.set_output_disable(false);
+        }
+        as_bytes(&self.setup)
+    }
+
+    /// A single clock cycle, shifting the results along by one bit
+    pub fn shift(&self) -> &[u8] {
+        as_bytes(&self.shift)
+    }
+
+    /// Switch the drivers back to normal mode. The display needs to be redrawn afterwards.
+    pub fn finish(&self) -> &[u8] {
+        as_bytes(&self.finish)
+    }
+}
+
+impl<const PANELS: usize> Default for ErrorDetection<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn as_bytes<T>(data: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(data as *const _ as *const u8, size_of_val(data)) }
+}
+
+/// Per-pixel results of error detection, in display coordinates (i.e. with the orientation of the
+/// framebuffer applied, so panel 0 is the leftmost one as the sign is mounted)
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FaultMap<const PANELS: usize = 2> {
+    panels: [[[Fault; PANEL_WIDTH]; PANEL_HEIGHT]; PANELS],
+}
+
+impl<const PANELS: usize> FaultMap<PANELS> {
+    pub fn new() -> Self {
+        Self {
+            panels: [[[Fault::None; _]; _]; PANELS],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Fault {
+        self.panels[x / PANEL_WIDTH][y][x % PANEL_WIDTH]
+    }
+
+    /// Record status bit `bit` read back from SDO after testing scan row `row` (0 being the first
+    /// bit read), mapping it to a pixel the same way `fb` draws
+    pub fn record(
+        &mut self,
+        fb: &DmaFrameBuffer<PANELS>,
+        row: usize,
+        test: Test,
+        bit: usize,
+        ok: bool,
+    ) {
+        if ok {
+            return;
+        }
+        // the first bit read is the one furthest down the chain, which is the first one shifted in
+        // when drawing
+        let (x, y) = fb.pixel_at(row, bit);
+        self.panels[x / PANEL_WIDTH][y][x % PANEL_WIDTH] = match test {
+            Test::Open => Fault::Open,
+            Test::Short => Fault::Short,
+        };
+    }
+
+    /// Positions and faults of all faulty LEDs
+    pub fn faults(&self) -> impl Iterator<Item = (usize, usize, Fault)> + '_ {
+        self.panels.iter().enumerate().flat_map(|(panel, rows)| {
+            rows.iter().enumerate().flat_map(move |(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, &fault)| fault != Fault::None)
+                    .map(move |(x, &fault)| (panel * PANEL_WIDTH + x, y, fault))
+            })
+        })
+    }
+
+    /// Number of panels covered
+    pub fn panels(&self) -> usize {
+        PANELS
+    }
+
+    /// Number of open and shorted LEDs on `panel`
+    pub fn panel_counts(&self, panel: usize) -> (usize, usize) {
+        let faults = self.panels[panel].as_flattened();
+        let count = |f| faults.iter().filter(|&&fault| fault == f).count();
+        (count(Fault::Open), count(Fault::Short))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faults().next().is_none()
+    }
+
+    /// Write a human readable report: a summary line per panel, then every faulty LED. The
+    /// summary comes first so that it survives if `w` runs out of space.
+    pub fn write_report(&self, w: &mut impl Write) -> core::fmt::Result {
+        for panel in 0..self.panels() {
+            let (open, short) = self.panel_counts(panel);
+            writeln!(w, "panel {}: {} open, {} short", panel, open, short)?;
+        }
+        for (x, y, fault) in self.faults() {
+            writeln!(w, "{},{}: {:?}", x, y, fault)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const PANELS: usize> Default for FaultMap<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
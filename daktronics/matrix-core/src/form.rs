@@ -0,0 +1,25 @@
+use heapless::{String, Vec};
+use percent_encoding::percent_decode_str;
+
+/// Find the value of `key` in the urlencoded form body of an HTTP request
+pub fn form_field<'a>(request: &'a str, key: &str) -> Option<&'a str> {
+    request[request.find("\r\n\r\n")? + 4..]
+        .split('&')
+        .find_map(|v| {
+            let mut s = v.split('=');
+            if s.next()? == key {
+                s.next()
+            } else {
+                None
+            }
+        })
+}
+
+/// Percent-decode a form value, failing if it doesn't fit in `N` bytes or isn't valid UTF-8
+pub fn decode_field<const N: usize>(value: &str) -> Option<String<N>> {
+    let mut decoded = Vec::<u8, N>::new();
+    for b in percent_decode_str(value) {
+        decoded.push(b).ok()?;
+    }
+    String::from_utf8(decoded).ok()
+}
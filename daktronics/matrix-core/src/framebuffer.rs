@@ -0,0 +1,592 @@
+/// This code is in part based on liebman's esp-hub75 driver, which is copyright esp-rs 2021,
+/// licensed under `MIT OR Apache-2.0`.
+/// https://github.com/liebman/esp-hub75/blob/8c738d7977f640caebde9b985435b803206586ff/src/framebuffer/plain.rs
+///
+/// The framebuffer holds the pixel data as bit planes, packed 8 bits of the shift register chain
+/// to a byte. Drivers that shift the pixel data out on its own send it as is, and the ones that
+/// clock the control signals out along with it encode it with a `DmaEncoding` first.
+///
+/// Grayscale uses binary code modulation (BCM): the framebuffer holds one bit plane per bit of
+/// brightness, and each plane lights its rows for a time proportional to the weight of its bit.
+/// The lower planes get a shorter output-enable window inside the row, and the planes above
+/// `FULL_PLANE` (which already use the whole window) are sent several times per refresh instead.
+use crate::calibration::GainMap;
+use crate::dither::{quantize_pixel, temporal_offset, Dithering, Quantizer, TEMPORAL_BITS};
+use crate::encoding::DmaEncoding;
+use crate::gamma::GammaLut;
+use crate::timing::DisplayTiming;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// Width of a single LED matrix panel
+pub const PANEL_WIDTH: usize = 48;
+/// Height of a single LED matrix panel
+pub const PANEL_HEIGHT: usize = 16;
+/// Number of rows selected by the row decoder. Each panel is made of two quadrants that are
+/// scanned in parallel, so a scan row lights two physical rows of every panel.
+pub const ROWS: usize = PANEL_HEIGHT / 2;
+/// Number of shift register bits per panel that are clocked in for every scan row
+pub const PANEL_CHAIN: usize = PANEL_WIDTH * 2;
+/// Longest chain of panels that a framebuffer can be created for
+pub const MAX_PANELS: usize = 8;
+/// Number of bit planes, i.e. bits of grayscale
+pub const BITS: u8 = 6;
+/// Highest bit plane that is shown in a single transfer. Every plane above this one is repeated,
+/// doubling its transfer count per bit, since its on-time wouldn't fit in a single row otherwise.
+pub(crate) const FULL_PLANE: usize = BITS as usize - 2;
+
+/// Number of times a bit plane is sent per refresh
+pub(crate) const fn plane_repeats(plane: usize) -> usize {
+    if plane > FULL_PLANE {
+        1 << (plane - FULL_PLANE)
+    } else {
+        1
+    }
+}
+
+/// The pixel data of every bit plane: one bit per position in the chain, for every scan row,
+/// packed most significant bit first in the order the bits are shifted in
+pub(crate) type BitPlanes<const PANELS: usize> =
+    [[[[u8; PANEL_CHAIN / 8]; PANELS]; ROWS]; BITS as usize];
+
+/// Number of transfers in one full refresh of the display
+pub const REFRESH_TRANSFERS: usize = {
+    let mut transfers = 0;
+    let mut plane = 0;
+    while plane < BITS as usize {
+        transfers += plane_repeats(plane);
+        plane += 1;
+    }
+    transfers
+};
+
+/// Describes how the panels of a sign are wired together into one shift register chain.
+///
+/// Panels are grouped into assemblies. Within an assembly the chain runs across the top quadrants
+/// of every panel, then back across the bottom quadrants; assemblies are chained left to right.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Geometry {
+    /// Number of panels in each assembly
+    pub panels_per_assembly: usize,
+}
+
+impl Geometry {
+    /// The AF-6700 assembly: two 16×48 panels wired as one 8×192 serpentine (see led-matrices.md)
+    pub const AF6700: Self = Self {
+        panels_per_assembly: 2,
+    };
+
+    /// Map a scan row and position in the chain to a (physical) pixel position
+    fn unmap(&self, row: usize, n: usize) -> (usize, usize) {
+        let assembly_width = self.panels_per_assembly * PANEL_WIDTH;
+        let (assembly, n) = (n / (assembly_width * 2), n % (assembly_width * 2));
+        let (half, x) = (n / assembly_width, n % assembly_width);
+        (assembly * assembly_width + x, half * ROWS + row)
+    }
+
+    /// Map a (physical) pixel position to its scan row and position in the chain
+    fn map(&self, x: usize, y: usize) -> (usize, usize) {
+        let assembly_width = self.panels_per_assembly * PANEL_WIDTH;
+        let (assembly, x) = (x / assembly_width, x % assembly_width);
+        let (half, row) = (y / ROWS, y % ROWS);
+        (
+            row,
+            assembly * assembly_width * 2 + half * assembly_width + x,
+        )
+    }
+}
+
+/// Rotation of the display, in degrees clockwise
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rotation {
+    Deg0,
+    Deg180,
+}
+
+/// How the sign is mounted, applied to every pixel before it's mapped onto the chain.
+///
+/// The default is rotated by 180°, which is how the panels are mounted in the AF-6700 enclosure.
+///
+/// This is stored in the config store as the rotation in degrees, optionally followed by `h` and/or
+/// `v` for horizontal/vertical mirroring, e.g. `180` or `0h`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    /// Mirror left to right
+    pub mirror_horizontal: bool,
+    /// Mirror top to bottom
+    pub mirror_vertical: bool,
+}
+
+impl Orientation {
+    /// Whether pixel columns end up in reverse order
+    fn flips_x(&self) -> bool {
+        (self.rotation == Rotation::Deg180) != self.mirror_horizontal
+    }
+
+    /// Whether pixel rows end up in reverse order
+    fn flips_y(&self) -> bool {
+        (self.rotation == Rotation::Deg180) != self.mirror_vertical
+    }
+
+    /// Map a pixel position on a `width`×`height` display to its physical position
+    fn apply(&self, x: usize, y: usize, width: usize, height: usize) -> (usize, usize) {
+        (
+            if self.flips_x() { width - 1 - x } else { x },
+            if self.flips_y() { height - 1 - y } else { y },
+        )
+    }
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Self {
+            rotation: Rotation::Deg180,
+            mirror_horizontal: false,
+            mirror_vertical: false,
+        }
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let flags_start = s.find(['h', 'v']).unwrap_or(s.len());
+        let (rotation, flags) = s.split_at(flags_start);
+        let rotation = match rotation {
+            "0" => Rotation::Deg0,
+            "180" => Rotation::Deg180,
+            _ => return Err(()),
+        };
+        if !flags.chars().all(|c| c == 'h' || c == 'v') {
+            return Err(());
+        }
+        Ok(Self {
+            rotation,
+            mirror_horizontal: flags.contains('h'),
+            mirror_vertical: flags.contains('v'),
+        })
+    }
+}
+
+impl Display for Orientation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.rotation {
+            Rotation::Deg0 => write!(f, "0")?,
+            Rotation::Deg180 => write!(f, "180")?,
+        }
+        if self.mirror_horizontal {
+            write!(f, "h")?;
+        }
+        if self.mirror_vertical {
+            write!(f, "v")?;
+        }
+        Ok(())
+    }
+}
+
+/// A framebuffer for a chain of `PANELS` panels, laid out side by side. By default this is a single
+/// AF-6700 assembly.
+#[derive(Copy, Clone)]
+pub struct DmaFrameBuffer<const PANELS: usize = 2> {
+    bit_planes: BitPlanes<PANELS>,
+    geometry: Geometry,
+    orientation: Orientation,
+    gamma: GammaLut,
+    dithering: Dithering,
+    /// `gamma` scaled for `dithering` to quantize with, looked up for every pixel drawn
+    levels: [u16; 256],
+    /// The level of every bit of the chain, including the temporal dithering bits, which are
+    /// re-encoded into the bit planes on every `advance_temporal_dither`
+    fine_levels: [[[u8; PANEL_CHAIN]; PANELS]; ROWS],
+    temporal_phase: u8,
+    /// Uniformity calibration, applied when quantizing
+    calibration: GainMap<PANELS>,
+    brightness: u8,
+    timing: DisplayTiming,
+}
+impl<const PANELS: usize> DmaFrameBuffer<PANELS> {
+    /// Width of the display in pixels
+    pub const WIDTH: usize = PANELS * PANEL_WIDTH;
+    /// Height of the display in pixels
+    pub const HEIGHT: usize = PANEL_HEIGHT;
+    /// Number of bits in the whole shift register chain
+    const COLS: usize = PANELS * PANEL_CHAIN;
+
+    pub fn new() -> Self {
+        Self::with_geometry(Geometry::AF6700)
+    }
+
+    pub fn with_geometry(geometry: Geometry) -> Self {
+        const {
+            assert!(PANELS > 0 && PANELS <= MAX_PANELS);
+        }
+        assert_eq!(
+            PANELS % geometry.panels_per_assembly,
+            0,
+            "panels must make up whole assemblies"
+        );
+        Self {
+            bit_planes: [[[[0; _]; _]; _]; _],
+            geometry,
+            orientation: Orientation::default(),
+            gamma: GammaLut::default(),
+            dithering: Dithering::default(),
+            levels: Dithering::default().scaled_levels(&GammaLut::default()),
+            fine_levels: [[[0; _]; _]; _],
+            temporal_phase: 0,
+            calibration: GainMap::new(),
+            brightness: u8::MAX,
+            timing: DisplayTiming::default(),
+        }
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    pub fn gamma(&self) -> &GammaLut {
+        &self.gamma
+    }
+
+    /// Change the curve used to map colors to LED intensity. This only applies to pixels drawn
+    /// afterwards.
+    pub fn set_gamma(&mut self, gamma: GammaLut) {
+        self.gamma = gamma;
+        self.levels = self.dithering.scaled_levels(&gamma);
+    }
+
+    pub fn dithering(&self) -> Dithering {
+        self.dithering
+    }
+
+    /// Change how colors are quantized to the bit planes. This only applies to pixels drawn
+    /// afterwards.
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        self.dithering = dithering;
+        self.levels = dithering.scaled_levels(&self.gamma);
+    }
+
+    pub fn calibration(&self) -> &GainMap<PANELS> {
+        &self.calibration
+    }
+
+    /// Change the uniformity calibration. This only applies to pixels drawn afterwards.
+    pub fn set_calibration(&mut self, calibration: GainMap<PANELS>) {
+        self.calibration = calibration;
+    }
+
+    /// Move on to the next step of temporal dithering, re-encoding every pixel. The driver calls
+    /// this once per refresh cycle; it does nothing unless temporal dithering is on.
+    pub fn advance_temporal_dither(&mut self) {
+        if !self.dithering.temporal {
+            return;
+        }
+        self.temporal_phase = self.temporal_phase.wrapping_add(1);
+        for row in 0..ROWS {
+            for x in 0..Self::COLS {
+                self.encode_level(row, x);
+            }
+        }
+    }
+
+    /// Change the orientation of the display. This clears the framebuffer, since anything already
+    /// drawn was mapped using the old orientation.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.bit_planes = [[[[0; _]; _]; _]; _];
+        self.fine_levels = [[[0; _]; _]; _];
+    }
+
+    /// The longest a row can be lit for while the next row is being shifted in. The output window
+    /// starts on the second clock cycle, and has to end `blanking` cycles before the latch.
+    fn max_on_time(timing: &DisplayTiming) -> usize {
+        let blanking = timing.blanking.max(timing.latch_width).min(Self::COLS - 2);
+        (Self::COLS - blanking - 2) * timing.on_time.min(100) as usize / 100
+    }
+
+    /// Number of clock cycles that the rows of a bit plane are lit for at full brightness
+    fn plane_on_time(plane: usize, timing: &DisplayTiming) -> usize {
+        let max_on_time = Self::max_on_time(timing);
+        if plane >= FULL_PLANE {
+            max_on_time
+        } else {
+            let shift = FULL_PLANE - plane;
+            // round to the nearest clock cycle
+            (max_on_time + (1 << (shift - 1))) >> shift
+        }
+    }
+
+    /// How long the rows of a bit plane are lit for at the current brightness
+    pub(crate) fn on_time(&self, plane: usize) -> usize {
+        self.on_time_at(plane, self.brightness)
+    }
+
+    fn on_time_at(&self, plane: usize, brightness: u8) -> usize {
+        Self::plane_on_time(plane, &self.timing) * brightness as usize / u8::MAX as usize
+    }
+
+    /// Clock cycles that every row of bit plane `plane` is lit for in one refresh, at the current
+    /// brightness. This is what drivers that don't send the raw data have to reproduce.
+    pub fn plane_lit_cycles(&self, plane: usize) -> usize {
+        self.on_time(plane) * plane_repeats(plane)
+    }
+
+    /// Clock cycles that LEDs are lit for in one refresh at `brightness`, summed over every LED.
+    /// Divided by `refresh_len`, this is the average number of LEDs lit at once, which is what the
+    /// display's average current draw goes with. The peak, while the busiest row is lit, can be
+    /// several times that.
+    pub fn led_cycles(&self, brightness: u8) -> u64 {
+        (0..BITS as usize)
+            .map(|plane| {
+                let data = self.bit_planes[plane].as_flattened().as_flattened();
+                let lit: u32 = data.iter().map(|byte| byte.count_ones()).sum();
+                let cycles = self.on_time_at(plane, brightness) * plane_repeats(plane);
+                lit as u64 * cycles as u64
+            })
+            .sum()
+    }
+
+    pub fn timing(&self) -> DisplayTiming {
+        self.timing
+    }
+
+    /// Whether `timing` leaves any time for the rows to be lit in this long a chain. Timings that
+    /// don't should be rejected before they're applied, since they'd leave the display dark.
+    pub fn supports_timing(timing: &DisplayTiming) -> bool {
+        timing.blanking < Self::COLS - 2
+    }
+
+    /// Change the blanking, latch and on-time parts of the display timing. The clock rate is up to
+    /// the driver.
+    pub fn set_timing(&mut self, timing: DisplayTiming) {
+        self.timing = timing;
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Set the global brightness by scaling how long each row is lit for
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    pub fn set_pixel(&mut self, p: Point, color: Gray8) {
+        if p.x < 0 || p.y < 0 {
+            return;
+        }
+        self.set_pixel_internal(p.x as usize, p.y as usize, color);
+    }
+
+    pub fn set_pixel_internal(&mut self, x: usize, y: usize, color: Gray8) {
+        if x >= Self::WIDTH || y >= Self::HEIGHT {
+            return;
+        }
+        let gain = self.calibration.gain(x, y);
+        let level = quantize_pixel(self.dithering, &self.levels, x, y, color.luma(), gain);
+        let (x, y) = self.orientation.apply(x, y, Self::WIDTH, Self::HEIGHT);
+        let (row, x) = self.geometry.map(x, y);
+        self.write_level(row, x, level);
+    }
+
+    /// The pixel that position `n` of the chain drives in scan row `row`. This is the inverse of
+    /// the mapping used for drawing.
+    pub(crate) fn pixel_at(&self, row: usize, n: usize) -> (usize, usize) {
+        let (x, y) = self.geometry.unmap(row, n);
+        // mirroring and rotating by 180° are their own inverse
+        self.orientation.apply(x, y, Self::WIDTH, Self::HEIGHT)
+    }
+
+    /// The area of the display, as it's drawn to, that panel `panel` covers. Panels are counted
+    /// from the start of the chain.
+    pub(crate) fn panel_area(&self, panel: usize) -> Rectangle {
+        let (left, right) = (panel * PANEL_WIDTH, (panel + 1) * PANEL_WIDTH - 1);
+        let corner = |x, y| {
+            let (x, y) = self.orientation.apply(x, y, Self::WIDTH, Self::HEIGHT);
+            Point::new(x as i32, y as i32)
+        };
+        Rectangle::with_corners(corner(left, 0), corner(right, Self::HEIGHT - 1))
+    }
+
+    /// Set bit `x` of the chain for scan row `row` to `level`, which includes the temporal
+    /// dithering bits. `fine_levels` doubles as a shadow copy of the bit planes, so pixels that
+    /// keep their level aren't re-encoded, which makes redrawing a mostly unchanged frame cheap.
+    fn write_level(&mut self, row: usize, x: usize, level: u8) {
+        let fine_level = &mut self.fine_levels[row][x / PANEL_CHAIN][x % PANEL_CHAIN];
+        if *fine_level == level {
+            return;
+        }
+        *fine_level = level;
+        self.encode_level(row, x);
+    }
+
+    /// Encode the level of bit `x` of the chain for scan row `row` into all bit planes, at the
+    /// current temporal dithering phase
+    fn encode_level(&mut self, row: usize, x: usize) {
+        let level = self.fine_levels[row][x / PANEL_CHAIN][x % PANEL_CHAIN];
+        let level = (level + temporal_offset(self.temporal_phase, row, x)) >> TEMPORAL_BITS;
+        let (byte, mask) = (x / 8, 0x80 >> (x % 8));
+        for (plane, rows) in self.bit_planes.iter_mut().enumerate() {
+            let data = &mut rows[row].as_flattened_mut()[byte];
+            if level & (1 << plane) != 0 {
+                *data |= mask;
+            } else {
+                *data &= !mask;
+            }
+        }
+    }
+
+    /// Draw a horizontal run of pixels starting at (`x`, `y`), which must fit on the display,
+    /// quantizing them with `quantizer`.
+    ///
+    /// The orientation and scan row are only worked out once for the whole run, so this is a lot
+    /// cheaper than drawing the pixels one by one.
+    fn write_span(
+        &mut self,
+        x: usize,
+        y: usize,
+        quantizer: &mut Quantizer,
+        lumas: impl IntoIterator<Item = u8>,
+    ) {
+        let physical_y = self.orientation.apply(0, y, Self::WIDTH, Self::HEIGHT).1;
+        let flip = self.orientation.flips_x();
+        let geometry = self.geometry;
+        let row = geometry.map(0, physical_y).0;
+        for (x, luma) in (x..).zip(lumas) {
+            let level = quantizer.quantize(x, y, luma, self.calibration.gain(x, y));
+            let x = if flip { Self::WIDTH - 1 - x } else { x };
+            self.write_level(row, geometry.map(x, physical_y).1, level);
+        }
+    }
+
+    /// Draw a full frame of `Gray8` pixels, `WIDTH * HEIGHT` bytes in row-major order
+    pub fn blit_gray8(&mut self, data: &[u8]) {
+        self.blit_gray8_area(&self.bounding_box(), data);
+    }
+
+    /// Draw `Gray8` pixels into `area`, row-major with one byte per pixel. Any part of `area` that
+    /// is off the display is skipped.
+    pub fn blit_gray8_area(&mut self, area: &Rectangle, data: &[u8]) {
+        let width = area.size.width as usize;
+        assert_eq!(data.len(), width * area.size.height as usize);
+        let clipped = area.intersection(&self.bounding_box());
+        if clipped.is_zero_sized() {
+            return;
+        }
+        let offset = clipped.top_left - area.top_left;
+        let (start, len) = (offset.x as usize, clipped.size.width as usize);
+        let (left, top) = (clipped.top_left.x as usize, clipped.top_left.y as usize);
+        let levels = self.levels;
+        let mut quantizer = Quantizer::new(self.dithering, &levels, left, top);
+        for (y, line) in clipped
+            .rows()
+            .zip(data.chunks_exact(width).skip(offset.y as usize))
+        {
+            let line = line[start..start + len].iter().copied();
+            self.write_span(left, y as usize, &mut quantizer, line);
+        }
+    }
+
+    /// The bits of scan row `row` in bit plane `plane`, in the order they're shifted into the chain
+    pub fn row_bits(&self, plane: usize, row: usize) -> impl Iterator<Item = bool> + '_ {
+        self.row_data(plane, row)
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |bit| byte & (0x80 >> bit) != 0))
+    }
+
+    /// The bits of scan row `row` in bit plane `plane`, packed 8 to a byte in the order they're
+    /// shifted into the chain, most significant bit first. This is ready to send with SPI, for
+    /// drivers that shift the pixel data out on its own.
+    pub fn row_data(&self, plane: usize, row: usize) -> &[u8] {
+        self.bit_planes[plane][row].as_flattened()
+    }
+
+    pub(crate) fn bit_planes(&self) -> &BitPlanes<PANELS> {
+        &self.bit_planes
+    }
+
+    /// Number of bytes (i.e. clock cycles) in one full refresh of the display, once it's encoded
+    /// with a `DmaEncoding`
+    pub const fn refresh_len() -> usize {
+        DmaEncoding::<PANELS>::REFRESH_LEN
+    }
+
+    /// All of the bit planes, in order
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bit_planes.as_flattened().as_flattened().as_flattened()
+    }
+}
+
+impl<const PANELS: usize> Default for DmaFrameBuffer<PANELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PANELS: usize> OriginDimensions for DmaFrameBuffer<PANELS> {
+    fn size(&self) -> Size {
+        Size::new(Self::WIDTH as u32, Self::HEIGHT as u32)
+    }
+}
+
+impl<const PANELS: usize> DrawTarget for DmaFrameBuffer<PANELS> {
+    type Color = Gray8;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for pixel in pixels {
+            self.set_pixel_internal(pixel.0.x as usize, pixel.0.y as usize, pixel.1);
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        if area.intersection(&self.bounding_box()) != *area {
+            // the colors of the clipped pixels would have to be skipped
+            return self.draw_iter(area.points().zip(colors).map(|(p, c)| Pixel(p, c)));
+        }
+        let mut colors = colors.into_iter();
+        let (left, top) = (area.top_left.x as usize, area.top_left.y as usize);
+        let levels = self.levels;
+        let mut quantizer = Quantizer::new(self.dithering, &levels, left, top);
+        for y in area.rows() {
+            let line = colors
+                .by_ref()
+                .take(area.size.width as usize)
+                .map(|c| c.luma());
+            self.write_span(left, y as usize, &mut quantizer, line);
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let (left, top) = (area.top_left.x as usize, area.top_left.y as usize);
+        let levels = self.levels;
+        let mut quantizer = Quantizer::new(self.dithering, &levels, left, top);
+        for y in area.rows() {
+            let line = core::iter::repeat_n(color.luma(), area.size.width as usize);
+            self.write_span(left, y as usize, &mut quantizer, line);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_solid(&self.bounding_box(), color)
+    }
+}
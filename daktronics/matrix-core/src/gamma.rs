@@ -0,0 +1,106 @@
+use core::str::FromStr;
+
+/// Lookup table mapping `Gray8` values to linear LED intensity (`0..=u16::MAX`).
+///
+/// The LEDs' brightness is proportional to their on-time, but our eyes aren't linear, so content
+/// needs to go through a curve like this before it's quantized to the panel's bit planes.
+///
+/// Stored as `linear`, `cie1931`, or a custom curve: the intensities (in ‰) of at least two evenly
+/// spaced `Gray8` values from black to white, which are interpolated between, e.g.
+/// `0,20,100,300,1000`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GammaLut([u16; 256]);
+
+impl GammaLut {
+    /// No correction at all
+    pub const LINEAR: Self = Self::linear();
+
+    /// CIE 1931 lightness, which treats `Gray8` values as perceived lightness (L*)
+    pub const CIE1931: Self = Self::cie1931();
+
+    /// Load a custom curve
+    pub const fn new(curve: [u16; 256]) -> Self {
+        Self(curve)
+    }
+
+    /// Build a curve from a function of the input value
+    pub fn from_fn(f: impl Fn(u8) -> u16) -> Self {
+        Self(core::array::from_fn(|i| f(i as u8)))
+    }
+
+    const fn linear() -> Self {
+        let mut curve = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            curve[i] = (i * 257) as u16;
+            i += 1;
+        }
+        Self(curve)
+    }
+
+    const fn cie1931() -> Self {
+        let mut curve = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let l = i as f32 * 100.0 / 255.0;
+            let y = if l <= 8.0 {
+                l / 903.3
+            } else {
+                let t = (l + 16.0) / 116.0;
+                t * t * t
+            };
+            curve[i] = (y * u16::MAX as f32 + 0.5) as u16;
+            i += 1;
+        }
+        Self(curve)
+    }
+
+    /// Linear intensity of a `Gray8` value
+    pub fn intensity(&self, luma: u8) -> u16 {
+        self.0[luma as usize]
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseGammaError;
+
+impl FromStr for GammaLut {
+    type Err = ParseGammaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => return Ok(Self::LINEAR),
+            "cie1931" => return Ok(Self::CIE1931),
+            _ => {}
+        }
+        let mut points = heapless::Vec::<u32, 32>::new();
+        for point in s.split(',') {
+            let point = point.trim().parse().map_err(|_| ParseGammaError)?;
+            if point > 1000 {
+                return Err(ParseGammaError);
+            }
+            points.push(point).map_err(|_| ParseGammaError)?;
+        }
+        let segments = points
+            .len()
+            .checked_sub(1)
+            .filter(|&n| n > 0)
+            .ok_or(ParseGammaError)?;
+        Ok(Self::from_fn(|luma| {
+            // position along the curve, in 1/255ths of a segment
+            let position = luma as usize * segments;
+            let (i, fraction) = (position / 255, (position % 255) as u32);
+            let start = points[i];
+            let end = points.get(i + 1).copied().unwrap_or(start);
+            // in 1/255ths of a ‰
+            let intensity = (start * (255 - fraction) + end * fraction) as u64;
+            ((intensity * u16::MAX as u64 + 255 * 1000 / 2) / (255 * 1000)) as u16
+        }))
+    }
+}
+
+impl Default for GammaLut {
+    fn default() -> Self {
+        Self::CIE1931
+    }
+}
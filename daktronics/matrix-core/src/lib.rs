@@ -0,0 +1,20 @@
+#![no_std]
+//! Hardware-independent parts of the matrix controller firmware, so that they can be built and
+//! tested on a PC. The board crates (e.g. `matrix-controller-esp32`) build on top of this.
+
+pub mod arrivals;
+pub mod calibration;
+pub mod compositor;
+pub mod config;
+pub mod dimming;
+pub mod dither;
+pub mod double_buffer;
+pub mod encoding;
+pub mod error_detection;
+pub mod form;
+pub mod framebuffer;
+pub mod gamma;
+pub mod power;
+pub mod test_pattern;
+pub mod timing;
+pub mod watchdog;
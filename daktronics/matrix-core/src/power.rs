@@ -0,0 +1,260 @@
+/// Power and thermal budgeting: estimates the current the display draws from what's lit, and turns
+/// the global brightness down when that or the chip temperature goes over a limit.
+///
+/// Every lit LED sinks the column drivers' constant current for as long as its row is lit, so the
+/// average LED current is that current times the average number of LEDs lit at once (see
+/// `DmaFrameBuffer::led_cycles`). That goes up and down with the brightness, since the brightness
+/// scales the on-times.
+///
+/// This only limits the average current over a refresh, not the peak. While a row is lit the
+/// LEDs draw that row's share of the current, so content concentrated in a few rows peaks at
+/// several times the average, and the supply has to ride that out (or be sized for it).
+use crate::framebuffer::DmaFrameBuffer;
+use core::fmt::{Display, Formatter};
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicI32, AtomicU32, AtomicU8, Ordering};
+
+/// Temperatures in °C that the thermal limits can be set to
+const TEMPERATURES: RangeInclusive<i32> = -40..=150;
+
+/// Limits on the display's current draw and the chip temperature.
+///
+/// This is stored in the config store as
+/// `led_current_ua,base_current_ma,supply_mv,max_current_ma,throttle_temp,max_temp,min_brightness`,
+/// e.g. `15000,150,5000,2000,70,85,32`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PowerConfig {
+    /// Current through each lit LED in µA, which is set by the column drivers' REXT resistor
+    pub led_current_ua: u32,
+    /// Current drawn by everything other than the LEDs, in mA
+    pub base_current_ma: u32,
+    /// Supply voltage in mV, for estimating the power
+    pub supply_mv: u32,
+    /// Highest estimated average current to allow, in mA
+    pub max_current_ma: u32,
+    /// Chip temperature in °C above which the brightness starts being turned down
+    pub throttle_temp: i32,
+    /// Chip temperature in °C at which the brightness is all the way down to `min_brightness`
+    pub max_temp: i32,
+    /// Lowest brightness that the thermal limit turns the display down to
+    pub min_brightness: u8,
+}
+
+impl PowerConfig {
+    /// Limits for a 5 V, 2 A supply
+    pub const fn new() -> Self {
+        Self {
+            led_current_ua: 15_000,
+            base_current_ma: 150,
+            supply_mv: 5000,
+            max_current_ma: 2000,
+            throttle_temp: 70,
+            max_temp: 85,
+            min_brightness: 32,
+        }
+    }
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for PowerConfig {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(',');
+        let mut next = || fields.next().ok_or(());
+        let config = Self {
+            led_current_ua: next()?.parse().map_err(|_| ())?,
+            base_current_ma: next()?.parse().map_err(|_| ())?,
+            supply_mv: next()?.parse().map_err(|_| ())?,
+            max_current_ma: next()?.parse().map_err(|_| ())?,
+            throttle_temp: next()?.parse().map_err(|_| ())?,
+            max_temp: next()?.parse().map_err(|_| ())?,
+            min_brightness: next()?.parse().map_err(|_| ())?,
+        };
+        if fields.next().is_some()
+            || !TEMPERATURES.contains(&config.throttle_temp)
+            || !TEMPERATURES.contains(&config.max_temp)
+            || config.max_temp <= config.throttle_temp
+        {
+            return Err(());
+        }
+        Ok(config)
+    }
+}
+
+impl Display for PowerConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{}",
+            self.led_current_ua,
+            self.base_current_ma,
+            self.supply_mv,
+            self.max_current_ma,
+            self.throttle_temp,
+            self.max_temp,
+            self.min_brightness
+        )
+    }
+}
+
+/// Limits the brightness of the frame being shown to stay within a `PowerConfig`
+pub struct PowerBudget {
+    config: PowerConfig,
+    /// Estimated LED current of the current frame at full brightness, in µA
+    full_led_current_ua: u64,
+    /// The brightness the last `limit` allowed
+    limit: u8,
+}
+
+impl PowerBudget {
+    pub fn new(config: PowerConfig) -> Self {
+        Self {
+            config,
+            full_led_current_ua: 0,
+            limit: u8::MAX,
+        }
+    }
+
+    /// Work out what a new frame draws. This counts every lit LED, so it's only worth doing when
+    /// the frame changes; brightness changes are accounted for by `limit`.
+    pub fn set_frame<const PANELS: usize>(&mut self, fb: &DmaFrameBuffer<PANELS>) {
+        let lit_cycles = fb.led_cycles(u8::MAX);
+        let refresh_len = DmaFrameBuffer::<PANELS>::refresh_len() as u64;
+        self.full_led_current_ua = lit_cycles * self.config.led_current_ua as u64 / refresh_len;
+    }
+
+    /// Estimated current drawn at `brightness`, in mA
+    pub fn current_ma(&self, brightness: u8) -> u32 {
+        let led_current_ua = self.full_led_current_ua * brightness as u64 / u8::MAX as u64;
+        self.config
+            .base_current_ma
+            .saturating_add((led_current_ua / 1000).min(u32::MAX as u64) as u32)
+    }
+
+    /// Estimated power drawn at `brightness`, in mW
+    pub fn power_mw(&self, brightness: u8) -> u32 {
+        (self.current_ma(brightness) as u64 * self.config.supply_mv as u64 / 1000) as u32
+    }
+
+    /// The brightness to show the frame at, given the one that was asked for and the chip
+    /// temperature in °C, if it's known. Going over the current limit turns the brightness down
+    /// straight away, but it only comes back up by one step per call, so that it doesn't pump
+    /// with the content.
+    pub fn limit(&mut self, requested: u8, temperature: Option<i32>) -> u8 {
+        let target = requested
+            .min(self.current_limit())
+            .min(temperature.map_or(u8::MAX, |t| self.thermal_limit(t)));
+        self.limit = target.min(self.limit.saturating_add(1));
+        self.limit
+    }
+
+    /// Highest brightness that keeps the current within `max_current_ma`
+    fn current_limit(&self) -> u8 {
+        let led_budget_ua = (self
+            .config
+            .max_current_ma
+            .saturating_sub(self.config.base_current_ma)) as u64
+            * 1000;
+        if self.full_led_current_ua <= led_budget_ua {
+            return u8::MAX;
+        }
+        (led_budget_ua * u8::MAX as u64 / self.full_led_current_ua) as u8
+    }
+
+    /// Highest brightness at chip temperature `temperature`, going down in a straight line from
+    /// full brightness at `throttle_temp` to `min_brightness` at `max_temp`
+    fn thermal_limit(&self, temperature: i32) -> u8 {
+        let PowerConfig {
+            throttle_temp,
+            max_temp,
+            min_brightness,
+            ..
+        } = self.config;
+        // in i64, since the temperature comes from outside and the config can be built by hand
+        let span = (max_temp as i64 - throttle_temp as i64).max(1);
+        let t = (temperature as i64 - throttle_temp as i64).clamp(0, span);
+        let range = (u8::MAX - min_brightness) as i64;
+        (u8::MAX as i64 - range * t / span) as u8
+    }
+}
+
+/// Estimates kept by the matrix driver, and the temperature it's been told about, shared with
+/// whatever wants to report them
+#[derive(Debug)]
+pub struct PowerStats {
+    current_ma: AtomicU32,
+    power_mw: AtomicU32,
+    /// Brightness after limiting
+    brightness: AtomicU8,
+    /// Chip temperature in °C, or `i32::MIN` if it isn't known
+    temperature: AtomicI32,
+}
+
+impl PowerStats {
+    pub const fn new() -> Self {
+        Self {
+            current_ma: AtomicU32::new(0),
+            power_mw: AtomicU32::new(0),
+            brightness: AtomicU8::new(u8::MAX),
+            temperature: AtomicI32::new(i32::MIN),
+        }
+    }
+
+    /// Estimated current, in mA
+    pub fn current_ma(&self) -> u32 {
+        self.current_ma.load(Ordering::Relaxed)
+    }
+
+    /// Estimated power, in mW
+    pub fn power_mw(&self) -> u32 {
+        self.power_mw.load(Ordering::Relaxed)
+    }
+
+    /// The brightness the display is actually at, after limiting
+    pub fn brightness(&self) -> u8 {
+        self.brightness.load(Ordering::Relaxed)
+    }
+
+    /// Chip temperature in °C, if it's been measured
+    pub fn temperature(&self) -> Option<i32> {
+        Some(self.temperature.load(Ordering::Relaxed)).filter(|&t| t != i32::MIN)
+    }
+
+    /// Record the estimates for the frame being shown at `brightness`
+    pub fn record_estimate(&self, budget: &PowerBudget, brightness: u8) {
+        self.current_ma
+            .store(budget.current_ma(brightness), Ordering::Relaxed);
+        self.power_mw
+            .store(budget.power_mw(brightness), Ordering::Relaxed);
+        self.brightness.store(brightness, Ordering::Relaxed);
+    }
+
+    pub fn record_temperature(&self, temperature: i32) {
+        self.temperature.store(temperature, Ordering::Relaxed);
+    }
+}
+
+impl Default for PowerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for PowerStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "estimated current: {} mA", self.current_ma())?;
+        writeln!(f, "estimated power: {} mW", self.power_mw())?;
+        writeln!(f, "brightness: {}", self.brightness())?;
+        match self.temperature() {
+            Some(t) => writeln!(f, "chip temperature: {} °C", t),
+            None => writeln!(f, "chip temperature: unknown"),
+        }
+    }
+}
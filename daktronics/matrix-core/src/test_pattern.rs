@@ -0,0 +1,175 @@
+/// Built-in test patterns, for checking the wiring of a sign on site without any content to show.
+///
+/// The walks light LEDs by where they are in the hardware rather than on the display: the row walk
+/// lights one HEF4028 address at a time, which is two physical rows of every panel, and the column
+/// walk lights one bit of the shift register chain at a time, in every scan row. Following them
+/// across the sign shows whether the serpentine mapping matches the wiring, and a row or a bit
+/// that never lights up points at a dead row driver or a broken chain.
+use crate::framebuffer::{DmaFrameBuffer, PANEL_CHAIN, ROWS};
+use core::fmt::{Display, Formatter, Write};
+use core::str::FromStr;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TestPattern {
+    /// Every LED at full brightness
+    AllOn,
+    /// Alternating pixels, swapping over every step
+    Checkerboard,
+    /// Every LED driven by one row decoder address at a time
+    RowWalk,
+    /// Every LED driven by one bit of the chain at a time
+    ColumnWalk,
+    /// Each panel outlined, with its position in the chain (counting from 1) in the middle
+    PanelIds,
+    /// Black on the left to white on the right
+    GrayRamp,
+    /// Every other scan row lit, and a bar sweeping across the display. Ghosting in the dark rows
+    /// means the blanking is too short, and the bar jumps whenever frames are dropped.
+    RefreshTiming,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseTestPatternError;
+
+impl TestPattern {
+    pub const ALL: [Self; 7] = [
+        Self::AllOn,
+        Self::Checkerboard,
+        Self::RowWalk,
+        Self::ColumnWalk,
+        Self::PanelIds,
+        Self::GrayRamp,
+        Self::RefreshTiming,
+    ];
+
+    /// The pattern after this one in `ALL`, or `None` after the last one, for stepping through
+    /// them with a button
+    pub fn next(self) -> Option<Self> {
+        let i = Self::ALL.iter().position(|&p| p == self).unwrap();
+        Self::ALL.get(i + 1).copied()
+    }
+
+    /// What the pattern is called in commands, e.g. `row-walk`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AllOn => "all-on",
+            Self::Checkerboard => "checkerboard",
+            Self::RowWalk => "row-walk",
+            Self::ColumnWalk => "column-walk",
+            Self::PanelIds => "panel-ids",
+            Self::GrayRamp => "gray-ramp",
+            Self::RefreshTiming => "refresh-timing",
+        }
+    }
+
+    /// How long each step of the pattern is shown for, in ms, or `None` if it doesn't move
+    pub fn step_ms(self) -> Option<u64> {
+        match self {
+            Self::Checkerboard | Self::RowWalk => Some(1000),
+            Self::ColumnWalk => Some(100),
+            // one step per frame at 30 fps
+            Self::RefreshTiming => Some(33),
+            Self::AllOn | Self::PanelIds | Self::GrayRamp => None,
+        }
+    }
+
+    /// Draw step `step` of the pattern into `target`, covering all of it. The walks are mapped to
+    /// pixels the same way `fb` draws, and animated patterns start over once `step` goes past
+    /// their last step.
+    pub fn draw<D: DrawTarget<Color = Gray8>, const PANELS: usize>(
+        self,
+        target: &mut D,
+        fb: &DmaFrameBuffer<PANELS>,
+        step: usize,
+    ) -> Result<(), D::Error> {
+        let (width, height) = (
+            DmaFrameBuffer::<PANELS>::WIDTH,
+            DmaFrameBuffer::<PANELS>::HEIGHT,
+        );
+        let area = Rectangle::new(Point::zero(), Size::new(width as u32, height as u32));
+        let chain = PANELS * PANEL_CHAIN;
+        let chain_pixel = |row, n| {
+            let (x, y) = fb.pixel_at(row, n);
+            Pixel(Point::new(x as i32, y as i32), Gray8::WHITE)
+        };
+        match self {
+            Self::AllOn => target.clear(Gray8::WHITE),
+            Self::Checkerboard => target.fill_contiguous(
+                &area,
+                area.points().map(|p| {
+                    if ((p.x + p.y) as usize + step).is_multiple_of(2) {
+                        Gray8::WHITE
+                    } else {
+                        Gray8::BLACK
+                    }
+                }),
+            ),
+            Self::RowWalk => {
+                target.clear(Gray8::BLACK)?;
+                let row = step % ROWS;
+                target.draw_iter((0..chain).map(|n| chain_pixel(row, n)))
+            }
+            Self::ColumnWalk => {
+                target.clear(Gray8::BLACK)?;
+                let n = step % chain;
+                target.draw_iter((0..ROWS).map(|row| chain_pixel(row, n)))
+            }
+            Self::PanelIds => {
+                target.clear(Gray8::BLACK)?;
+                let text_style = TextStyleBuilder::new()
+                    .alignment(Alignment::Center)
+                    .baseline(Baseline::Middle)
+                    .build();
+                let font = MonoTextStyle::new(&FONT_6X10, Gray8::WHITE);
+                let mut number = heapless::String::<4>::new();
+                for panel in 0..PANELS {
+                    let area = fb.panel_area(panel);
+                    area.into_styled(PrimitiveStyle::with_stroke(Gray8::WHITE, 1))
+                        .draw(target)?;
+                    number.clear();
+                    let _ = write!(number, "{}", panel + 1);
+                    Text::with_text_style(&number, area.center(), font, text_style).draw(target)?;
+                }
+                Ok(())
+            }
+            Self::GrayRamp => target.fill_contiguous(
+                &area,
+                (0..width * height).map(|i| Gray8::new((i % width * 255 / (width - 1)) as u8)),
+            ),
+            Self::RefreshTiming => {
+                target.clear(Gray8::BLACK)?;
+                let lit = (0..ROWS)
+                    .step_by(2)
+                    .flat_map(|row| (0..chain).map(move |n| (row, n)));
+                target.draw_iter(lit.map(|(row, n)| chain_pixel(row, n)))?;
+                let x = (step % width) as i32;
+                Rectangle::new(Point::new(x, 0), Size::new(1, height as u32))
+                    .into_styled(PrimitiveStyle::with_fill(Gray8::WHITE))
+                    .draw(target)
+            }
+        }
+    }
+}
+
+impl FromStr for TestPattern {
+    type Err = ParseTestPatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|pattern| pattern.name() == s)
+            .ok_or(ParseTestPatternError)
+    }
+}
+
+impl Display for TestPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,175 @@
+use core::cell::Cell;
+/// Display timing and refresh statistics, for tuning out ghosting and camera flicker on a
+/// particular batch of panels.
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// Timing of the signals sent to the matrix.
+///
+/// This is stored in the config store as `clock_khz,blanking,latch_width,on_time`, e.g.
+/// `1000,25,1,100`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DisplayTiming {
+    /// Rate of the clock that shifts data into the matrix
+    pub clock_khz: u32,
+    /// Clock cycles that the display stays blanked for before the next row is latched, to give
+    /// the row drivers time to turn off. Too few causes ghosting.
+    pub blanking: usize,
+    /// Clock cycles that LE/MOD is held high for at the end of every row. This has to fit inside
+    /// `blanking`.
+    pub latch_width: usize,
+    /// How long each row is lit for, as a percentage of the longest possible on-time
+    pub on_time: u8,
+}
+
+impl Default for DisplayTiming {
+    fn default() -> Self {
+        Self {
+            clock_khz: 1000,
+            blanking: 25,
+            latch_width: 1,
+            on_time: 100,
+        }
+    }
+}
+
+impl FromStr for DisplayTiming {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(',');
+        let mut next = || fields.next().ok_or(());
+        let timing = Self {
+            clock_khz: next()?.parse().map_err(|_| ())?,
+            blanking: next()?.parse().map_err(|_| ())?,
+            latch_width: next()?.parse().map_err(|_| ())?,
+            on_time: next()?.parse().map_err(|_| ())?,
+        };
+        if fields.next().is_some()
+            || timing.clock_khz == 0
+            || timing.latch_width == 0
+            || timing.latch_width > timing.blanking
+            || timing.on_time > 100
+        {
+            return Err(());
+        }
+        Ok(timing)
+    }
+}
+
+impl Display for DisplayTiming {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.clock_khz, self.blanking, self.latch_width, self.on_time
+        )
+    }
+}
+
+/// Counters kept by the matrix driver, shared with whatever wants to report them.
+///
+/// The counts are behind a critical section rather than atomics, since the ESP32-C3 has no atomic
+/// read-modify-write instructions.
+pub struct RefreshStats {
+    refreshes: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+    errors: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+    /// Refresh rate over the last measurement period, in mHz
+    refresh_rate: AtomicU32,
+    /// Rate the driver was restarted at over the last measurement period, in mHz
+    restart_rate: AtomicU32,
+    /// Share of the last measurement period spent sending refreshes, in ‰
+    refreshing: AtomicU32,
+}
+
+impl RefreshStats {
+    pub const fn new() -> Self {
+        Self {
+            refreshes: Mutex::new(Cell::new(0)),
+            errors: Mutex::new(Cell::new(0)),
+            refresh_rate: AtomicU32::new(0),
+            restart_rate: AtomicU32::new(0),
+            refreshing: AtomicU32::new(0),
+        }
+    }
+
+    /// Total number of refreshes sent to the display
+    pub fn refreshes(&self) -> u32 {
+        self.refreshes.lock(Cell::get)
+    }
+
+    /// Total number of failed transfers
+    pub fn errors(&self) -> u32 {
+        self.errors.lock(Cell::get)
+    }
+
+    /// Refresh rate actually achieved, including the time spent in between transfers, in mHz
+    pub fn refresh_rate_mhz(&self) -> u32 {
+        self.refresh_rate.load(Ordering::Relaxed)
+    }
+
+    /// How often the driver has to restart its transfer (once per `render`), in mHz. The display
+    /// goes idle for a moment every time, so the lower the better.
+    pub fn restart_rate_mhz(&self) -> u32 {
+        self.restart_rate.load(Ordering::Relaxed)
+    }
+
+    /// Share of the time spent sending refreshes, in ‰. The rest goes on restarting the transfer,
+    /// while the display is blanked, so it dims every pixel by the same amount (it doesn't affect
+    /// the uniformity) and flickers at the restart rate.
+    pub fn refreshing_permille(&self) -> u32 {
+        self.refreshing.load(Ordering::Relaxed)
+    }
+
+    /// Count `refreshes` refreshes, sent as one transfer that took `sending_us` µs out of
+    /// `elapsed_us` µs since the last one, and update the rates from them
+    pub fn record_refreshes(&self, refreshes: usize, sending_us: u64, elapsed_us: u64) {
+        let refreshes = u32::try_from(refreshes).unwrap_or(u32::MAX);
+        self.refreshes
+            .lock(|count| count.set(count.get().wrapping_add(refreshes)));
+        let per_second = |count: u64| {
+            let rate = (count * 1_000_000_000).checked_div(elapsed_us)?;
+            Some(rate.min(u32::MAX as u64) as u32)
+        };
+        if let (Some(refresh_rate), Some(restart_rate)) =
+            (per_second(refreshes as u64), per_second(1))
+        {
+            self.refresh_rate.store(refresh_rate, Ordering::Relaxed);
+            self.restart_rate.store(restart_rate, Ordering::Relaxed);
+        }
+        if let Some(refreshing) = (sending_us.min(elapsed_us) * 1000).checked_div(elapsed_us) {
+            self.refreshing.store(refreshing as u32, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_error(&self) {
+        self.errors
+            .lock(|count| count.set(count.get().wrapping_add(1)));
+    }
+}
+
+impl Default for RefreshStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for RefreshStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let (rate, restarts) = (self.refresh_rate_mhz(), self.restart_rate_mhz());
+        writeln!(f, "refreshes: {}", self.refreshes())?;
+        writeln!(f, "errors: {}", self.errors())?;
+        writeln!(f, "refresh rate: {}.{:03} Hz", rate / 1000, rate % 1000)?;
+        writeln!(
+            f,
+            "restart rate: {}.{:03} Hz",
+            restarts / 1000,
+            restarts % 1000
+        )?;
+        let refreshing = self.refreshing_permille();
+        writeln!(f, "refreshing: {}.{} %", refreshing / 10, refreshing % 10)
+    }
+}
@@ -0,0 +1,114 @@
+/// Software side of the watchdog: subsystems check in with a `Supervisor` regularly, and whatever
+/// feeds the hardware watchdog asks it whether any of them has stalled before doing so.
+///
+/// Times are in ms from any monotonic clock, and are allowed to wrap.
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// A part of the firmware that has to keep checking in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Subsystem {
+    /// The loop sending refreshes to the display
+    Render,
+    /// The wifi connection
+    Network,
+    /// The arrivals board
+    Arrivals,
+    /// The video playing over the arrivals board
+    Video,
+    /// Redrawing for a new uniformity calibration
+    Calibration,
+    /// Drawing the test patterns in diagnostic mode
+    TestPatterns,
+}
+
+impl Subsystem {
+    pub const ALL: [Self; 6] = [
+        Self::Render,
+        Self::Network,
+        Self::Arrivals,
+        Self::Video,
+        Self::Calibration,
+        Self::TestPatterns,
+    ];
+
+    /// How long it can go without checking in before it counts as stalled
+    pub const fn timeout_ms(self) -> u32 {
+        match self {
+            // refreshes take a few tens of ms
+            Self::Render => 1000,
+            // connecting can legitimately take a while
+            Self::Network => 60_000,
+            // the content sources each draw a frame at least every second or so while they're
+            // running, and pause while they're idle
+            Self::Arrivals | Self::Video | Self::Calibration | Self::TestPatterns => 5000,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Render => "render",
+            Self::Network => "network",
+            Self::Arrivals => "arrivals",
+            Self::Video => "video",
+            Self::Calibration => "calibration",
+            Self::TestPatterns => "test patterns",
+        }
+    }
+
+    /// Inverse of `self as u8`, for reading it back out of retained memory
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+}
+
+impl Display for Subsystem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Last check-in of every subsystem. A subsystem is only watched from its first check-in, so ones
+/// that aren't running (e.g. the network, when it's disabled) don't count as stalled.
+pub struct Supervisor {
+    watched: [AtomicBool; Subsystem::ALL.len()],
+    last_check_in: [AtomicU32; Subsystem::ALL.len()],
+}
+
+impl Supervisor {
+    pub const fn new() -> Self {
+        Self {
+            watched: [const { AtomicBool::new(false) }; _],
+            last_check_in: [const { AtomicU32::new(0) }; _],
+        }
+    }
+
+    /// Record that `subsystem` is still making progress, and start watching it
+    pub fn check_in(&self, subsystem: Subsystem, now_ms: u32) {
+        self.last_check_in[subsystem as usize].store(now_ms, Ordering::Relaxed);
+        self.watched[subsystem as usize].store(true, Ordering::Release);
+    }
+
+    /// Stop watching `subsystem` until it next checks in, for when it's about to legitimately go
+    /// quiet for a while
+    pub fn pause(&self, subsystem: Subsystem) {
+        self.watched[subsystem as usize].store(false, Ordering::Relaxed);
+    }
+
+    /// The first watched subsystem that hasn't checked in within its timeout, if any
+    pub fn stalled(&self, now_ms: u32) -> Option<Subsystem> {
+        Subsystem::ALL.into_iter().find(|&subsystem| {
+            self.watched[subsystem as usize].load(Ordering::Acquire)
+                && now_ms
+                    .wrapping_sub(self.last_check_in[subsystem as usize].load(Ordering::Relaxed))
+                    > subsystem.timeout_ms()
+        })
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,188 @@
+//! Arrivals board formatting, paging and scrolling
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use matrix_core::arrivals::{Arrival, ArrivalStatus, ArrivalsBoard};
+use matrix_core::compositor::Layer;
+
+fn arrival(route: &str, headsign: &str, minutes: &[u16], status: ArrivalStatus) -> Arrival {
+    Arrival::new(route, headsign, minutes, status).unwrap()
+}
+
+/// Columns of `layer` with anything lit in them
+fn lit_columns<const PANELS: usize>(layer: &Layer<PANELS>) -> Vec<i32> {
+    (0..Layer::<PANELS>::WIDTH as i32)
+        .filter(|&x| {
+            (0..Layer::<PANELS>::HEIGHT as i32)
+                .any(|y| layer.pixel(Point::new(x, y)).is_some_and(|p| p.luma() > 0))
+        })
+        .collect()
+}
+
+/// How `arrival` looks on its own, at the start of its page
+fn single(arrival: &Arrival) -> Layer {
+    let mut layer = Layer::new();
+    let mut board = ArrivalsBoard::<1>::new();
+    board.set_arrivals([arrival.clone()]);
+    board.draw(&mut layer, 0).unwrap();
+    layer
+}
+
+fn same(a: &Layer, b: &Layer) -> bool {
+    Rectangle::new(Point::zero(), Size::new(96, 16))
+        .points()
+        .all(|p| a.pixel(p) == b.pixel(p))
+}
+
+#[test]
+fn formatting() {
+    use ArrivalStatus::*;
+    let a = arrival("", "S.Waterfront", &[1, 15], OnTime);
+    assert_eq!(a.destination(), "To S.Waterfront");
+    assert_eq!(a.times(), "1 min & 15 min");
+    assert_eq!(
+        arrival("20", "Beaverton TC", &[], OnTime).destination(),
+        "20 To Beaverton TC"
+    );
+    for (minutes, status, expected) in [
+        (&[0, 12][..], OnTime, "Due & 12 min"),
+        (&[2, 12, 30], Due, "Due & 12 min"),
+        (&[7], OnTime, "7 min"),
+        (&[], OnTime, "No arrivals"),
+        (&[], Due, "Due"),
+        (&[5, 20], Delayed, "Delayed 5 min & 20 min"),
+        (&[], Delayed, "Delayed"),
+        (&[5, 20], Cancelled, "Cancelled"),
+    ] {
+        assert_eq!(arrival("", "x", minutes, status).times(), expected);
+    }
+    assert!(Arrival::new(&"x".repeat(100), "y", &[], OnTime).is_err());
+}
+
+#[test]
+fn pages() {
+    let mut board = ArrivalsBoard::<8>::new();
+    let routes = [
+        arrival("1", "A", &[1], ArrivalStatus::OnTime),
+        arrival("2", "B", &[2], ArrivalStatus::OnTime),
+        arrival("3", "C", &[3], ArrivalStatus::OnTime),
+    ];
+    board.set_arrivals(routes.clone());
+
+    // one route per assembly, so a single assembly shows them one at a time
+    let mut layer = Layer::<2>::new();
+    let mut shown = vec![];
+    for t in (0..20_000).step_by(1000) {
+        board.draw(&mut layer, t).unwrap();
+        shown.push(
+            routes
+                .iter()
+                .position(|a| same(&single(a), &layer))
+                .unwrap(),
+        );
+    }
+    assert_eq!(&shown[..5], [0; 5]);
+    assert_eq!(&shown[5..10], [1; 5]);
+    assert_eq!(&shown[10..15], [2; 5]);
+    assert_eq!(&shown[15..], [0; 5]);
+
+    // two assemblies show two routes side by side
+    let mut wide = Layer::<4>::new();
+    let mut board = ArrivalsBoard::<8>::new();
+    board.set_arrivals(routes);
+    board.draw(&mut wide, 0).unwrap();
+    let columns = lit_columns(&wide);
+    assert!(columns.iter().any(|&x| x < 96) && columns.iter().any(|&x| x >= 96));
+    // and then the third on its own
+    board.draw(&mut wide, 5000).unwrap();
+    assert!(lit_columns(&wide).iter().all(|&x| x < 96));
+}
+
+#[test]
+fn scrolling() {
+    let mut board = ArrivalsBoard::<8>::new();
+    board.set_arrivals([
+        arrival("", "Short", &[1], ArrivalStatus::OnTime),
+        arrival(
+            "",
+            "Portland International Airport",
+            &[4],
+            ArrivalStatus::OnTime,
+        ),
+    ]);
+    let mut layer = Layer::<2>::new();
+    board.draw(&mut layer, 0).unwrap();
+    // short lines are centered
+    let columns = lit_columns(&layer);
+    assert!(columns[0] > 10 && *columns.last().unwrap() < 85);
+
+    // the long page stays up until its headsign has scrolled all the way
+    board.draw(&mut layer, 5000).unwrap();
+    let top = |layer: &Layer<2>| -> Vec<_> {
+        (0..96)
+            .map(|x| (0..8).any(|y| layer.pixel(Point::new(x, y)).is_some_and(|p| p.luma() > 0)))
+            .collect()
+    };
+    let start = top(&layer);
+    board.draw(&mut layer, 6000).unwrap();
+    assert_eq!(top(&layer), start, "pauses before scrolling");
+    board.draw(&mut layer, 9000).unwrap();
+    let scrolled = top(&layer);
+    assert_ne!(scrolled, start);
+    board.draw(&mut layer, 11_700).unwrap();
+    let end = top(&layer);
+    assert_ne!(end, scrolled);
+    board.draw(&mut layer, 12_000).unwrap();
+    assert_eq!(top(&layer), end, "pauses at the end");
+    board.draw(&mut layer, 13_000).unwrap();
+    assert!(same(&layer, &single(&board.arrivals()[0])));
+}
+
+#[test]
+fn redraws() {
+    let routes = [
+        arrival("", "Short", &[1], ArrivalStatus::OnTime),
+        arrival(
+            "",
+            "Portland International Airport",
+            &[4],
+            ArrivalStatus::OnTime,
+        ),
+    ];
+    let mut board = ArrivalsBoard::<8>::new();
+    board.set_arrivals(routes.clone());
+    let mut layer = Layer::<2>::new();
+    assert!(board.draw(&mut layer, 0).unwrap());
+    // nothing moves, so nothing is drawn until the page changes
+    assert!(!board.draw(&mut layer, 33).unwrap());
+    assert!(!board.draw(&mut layer, 4999).unwrap());
+    assert!(board.draw(&mut layer, 5000).unwrap());
+    // or the long line scrolls
+    assert!(!board.draw(&mut layer, 6000).unwrap());
+
+    // redrawing just the lines that scroll looks the same as redrawing less often
+    let mut slow = board.clone();
+    let mut slow_layer = layer.clone();
+    for t in (6000..13_000).step_by(33) {
+        board.draw(&mut layer, t).unwrap();
+        if t % 1000 < 33 {
+            slow.draw(&mut slow_layer, t).unwrap();
+            assert!(same(&layer, &slow_layer), "{t}");
+        }
+    }
+
+    // and so do the lines that change
+    let mut routes = routes;
+    assert!(!board.draw(&mut layer, 13_100).unwrap());
+    board.set_arrivals(routes.clone());
+    assert!(!board.draw(&mut layer, 13_200).unwrap());
+    routes[0].minutes[0] = 12;
+    board.set_arrivals(routes.clone());
+    assert!(board.draw(&mut layer, 13_300).unwrap());
+    assert!(same(&layer, &single(&routes[0])));
+    // and a route that's gone is erased
+    let mut wide = Layer::<4>::new();
+    board.draw(&mut wide, 20_000).unwrap();
+    board.set_arrivals([routes[0].clone()]);
+    assert!(board.draw(&mut wide, 20_100).unwrap());
+    assert!(lit_columns(&wide).iter().all(|&x| x < 96));
+}
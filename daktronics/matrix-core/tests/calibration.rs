@@ -0,0 +1,139 @@
+//! Uniformity calibration: gains applied when quantizing, the upload form and saving to flash
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_storage::{ReadStorage, Storage};
+use matrix_core::calibration::{draw_pattern, GainMap, InvalidGains, UNITY_GAIN};
+use matrix_core::compositor::Layer;
+use matrix_core::encoding::DmaEncoding;
+use matrix_core::framebuffer::DmaFrameBuffer;
+use matrix_core::gamma::GammaLut;
+use matrix_emulator::Emulator;
+
+/// Flash-like storage in RAM, erased to all ones
+struct RamStorage(Vec<u8>);
+
+impl ReadStorage for RamStorage {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Storage for RamStorage {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        self.0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(())?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+fn form(body: &str) -> String {
+    format!("POST /calibration HTTP/1.1\r\n\r\n{body}")
+}
+
+#[test]
+fn gains_dim_pixels() {
+    let mut gains: GainMap = GainMap::new();
+    // the second panel at half brightness
+    gains.fill(&Rectangle::new(Point::new(48, 0), Size::new(48, 16)), 127);
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_orientation("0".parse().unwrap());
+    fb.set_gamma(GammaLut::LINEAR);
+    fb.set_calibration(gains);
+    draw_pattern(&mut fb, 255).unwrap();
+
+    let mut emulator = Emulator::af6700();
+    emulator.run(DmaEncoding::from(&fb).transfers());
+    let image = emulator.image();
+    // the same as the level half way up, 32 of 63 steps, and unity gain leaves the rest alone
+    let mut uncalibrated: DmaFrameBuffer = DmaFrameBuffer::new();
+    uncalibrated.set_orientation("0".parse().unwrap());
+    uncalibrated.set_gamma(GammaLut::LINEAR);
+    uncalibrated.set_pixel(Point::new(10, 3), Gray8::new(255));
+    uncalibrated.set_pixel(Point::new(60, 3), Gray8::new(130));
+    let mut emulator = Emulator::af6700();
+    emulator.run(DmaEncoding::from(&uncalibrated).transfers());
+    let expected = emulator.image();
+    assert_eq!(image.get(10, 3), expected.get(10, 3));
+    assert_eq!(image.get(60, 3), expected.get(60, 3));
+    assert!(image.get(60, 3) < image.get(10, 3));
+}
+
+#[test]
+fn forms() {
+    let mut gains = GainMap::<2>::new();
+    assert_eq!(gains.apply_form(&form("pattern=128")), Ok(false));
+    assert_eq!(
+        gains.apply_form(&form("area=48%2C0%2C48%2C16&gain=200")),
+        Ok(true)
+    );
+    assert_eq!(gains.gain(47, 15), UNITY_GAIN);
+    assert_eq!(gains.gain(48, 0), 200);
+    assert_eq!(gains.gain(95, 15), 200);
+
+    assert_eq!(gains.apply_form(&form("row=2&gains=0aff80")), Ok(true));
+    assert_eq!(
+        [
+            gains.gain(0, 2),
+            gains.gain(1, 2),
+            gains.gain(2, 2),
+            gains.gain(3, 2)
+        ],
+        [10, 255, 128, 255]
+    );
+
+    for bad in [
+        "area=1,2,3&gain=4",
+        "area=1,2,3,4",
+        "area=1,2,3,4&gain=256",
+        "row=16&gains=00",
+        "row=0&gains=0",
+        "row=0&gains=zz",
+    ] {
+        assert_eq!(gains.apply_form(&form(bad)), Err(InvalidGains), "{bad}");
+    }
+    let too_long = "00".repeat(97);
+    assert_eq!(
+        gains.apply_form(&form(&format!("row=0&gains={too_long}"))),
+        Err(InvalidGains)
+    );
+}
+
+#[test]
+fn save_and_load() {
+    let mut storage = RamStorage(vec![0xff; 4096]);
+    assert_eq!(GainMap::<2>::load(&mut storage, 0x100), Ok(None));
+
+    let mut gains = GainMap::<2>::new();
+    gains.fill(&Rectangle::new(Point::new(5, 0), Size::new(1, 16)), 77);
+    gains.save(&mut storage, 0x100).unwrap();
+    assert_eq!(GainMap::<2>::load(&mut storage, 0x100), Ok(Some(gains)));
+    // a map for a different number of panels doesn't fit
+    assert_eq!(GainMap::<4>::load(&mut storage, 0x100), Ok(None));
+}
+
+#[test]
+fn pattern_markers() {
+    let mut layer = Layer::<2>::new();
+    draw_pattern(&mut layer, 128).unwrap();
+    let dark = |x, y| layer.pixel(Point::new(x, y)) == Some(Gray8::BLACK);
+    // a corner of every column driver's patch
+    for (x, y) in [(0, 0), (8, 0), (40, 8), (88, 8)] {
+        assert!(dark(x, y), "({x}, {y})");
+    }
+    // the second panel's number
+    assert!(dark(48, 0) && dark(49, 0) && !dark(50, 0));
+    assert!(!dark(1, 0) && !dark(1, 1) && !dark(47, 15));
+    assert_eq!(layer.pixel(Point::new(10, 3)), Some(Gray8::new(128)));
+}
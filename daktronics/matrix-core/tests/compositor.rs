@@ -0,0 +1,220 @@
+//! Flatten layers and compare the result with drawing the expected pixels directly
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use matrix_core::compositor::Compositor;
+use matrix_core::framebuffer::DmaFrameBuffer;
+
+const W: usize = DmaFrameBuffer::<2>::WIDTH;
+const H: usize = DmaFrameBuffer::<2>::HEIGHT;
+
+fn fill(compositor: &mut Compositor<3>, layer: usize, area: Rectangle, luma: u8) {
+    area.into_styled(PrimitiveStyle::with_fill(Gray8::new(luma)))
+        .draw(compositor.layer_mut(layer))
+        .unwrap();
+}
+
+/// Flatten `compositor` into `fb` and check it against `expected`, a function of the position
+fn check(compositor: &mut Compositor<3>, fb: &mut DmaFrameBuffer, expected: impl Fn(Point) -> u8) {
+    compositor.flatten(fb);
+    let mut reference: DmaFrameBuffer = DmaFrameBuffer::new();
+    let pixels: Vec<u8> = reference.bounding_box().points().map(expected).collect();
+    reference.blit_gray8(&pixels);
+    assert!(fb.as_bytes() == reference.as_bytes());
+}
+
+#[test]
+fn empty() {
+    let mut fb = DmaFrameBuffer::new();
+    // whatever is in the framebuffer gets covered
+    fb.set_pixel(Point::new(3, 4), Gray8::WHITE);
+    check(&mut Compositor::new(), &mut fb, |_| 0);
+}
+
+#[test]
+fn z_order_and_transparency() {
+    let mut compositor = Compositor::new();
+    let mut fb = DmaFrameBuffer::new();
+    let left = Rectangle::new(Point::zero(), Size::new(20, 10));
+    let right = Rectangle::new(Point::new(10, 5), Size::new(20, 10));
+    fill(&mut compositor, 0, left, 200);
+    fill(&mut compositor, 1, right, 100);
+    // later layers are on top when they have the same z
+    check(&mut compositor, &mut fb, |p| match p {
+        p if right.contains(p) => 100,
+        p if left.contains(p) => 200,
+        _ => 0,
+    });
+
+    compositor.layer_mut(0).set_z(1);
+    check(&mut compositor, &mut fb, |p| match p {
+        p if left.contains(p) => 200,
+        p if right.contains(p) => 100,
+        _ => 0,
+    });
+
+    // drawing black is opaque, erasing isn't
+    fill(
+        &mut compositor,
+        0,
+        Rectangle::new(Point::zero(), Size::new(5, 5)),
+        0,
+    );
+    compositor
+        .layer_mut(0)
+        .erase(&Rectangle::new(Point::new(15, 5), Size::new(5, 5)));
+    check(&mut compositor, &mut fb, |p| match p {
+        p if p.x < 5 && p.y < 5 => 0,
+        p if p.x >= 15 && p.x < 20 && p.y >= 5 && p.y < 10 => 100,
+        p if left.contains(p) => 200,
+        p if right.contains(p) => 100,
+        _ => 0,
+    });
+    assert_eq!(compositor.layer(0).pixel(Point::new(16, 6)), None);
+    assert_eq!(
+        compositor.layer(0).pixel(Point::new(6, 6)),
+        Some(Gray8::new(200))
+    );
+}
+
+#[test]
+fn opacity_and_brightness() {
+    let mut compositor = Compositor::new();
+    let mut fb = DmaFrameBuffer::new();
+    let all = Rectangle::new(Point::zero(), Size::new(W as u32, H as u32));
+    fill(&mut compositor, 0, all, 200);
+    fill(&mut compositor, 2, all, 100);
+    compositor.layer_mut(2).set_opacity(51);
+    compositor.layer_mut(2).set_brightness(128);
+    // 100 dimmed to 50, covering a fifth of the 200 below
+    check(&mut compositor, &mut fb, |_| 170);
+}
+
+#[test]
+fn clip_and_visibility() {
+    let mut compositor = Compositor::new();
+    let mut fb = DmaFrameBuffer::new();
+    let all = Rectangle::new(Point::zero(), Size::new(W as u32, H as u32));
+    let clip = Rectangle::new(Point::new(40, 0), Size::new(16, 8));
+    fill(&mut compositor, 0, all, 50);
+    fill(&mut compositor, 1, all, 250);
+    compositor.layer_mut(1).set_clip(Some(clip));
+    check(&mut compositor, &mut fb, |p| {
+        if clip.contains(p) {
+            250
+        } else {
+            50
+        }
+    });
+
+    compositor.layer_mut(0).set_visible(false);
+    check(&mut compositor, &mut fb, |p| {
+        if clip.contains(p) {
+            250
+        } else {
+            0
+        }
+    });
+}
+
+#[test]
+fn blit() {
+    let mut compositor = Compositor::new();
+    let mut fb = DmaFrameBuffer::new();
+    let frame: Vec<u8> = (0..W * H).map(|i| (i % 251) as u8).collect();
+    compositor.layer_mut(1).blit_gray8(&frame);
+    fill(
+        &mut compositor,
+        2,
+        Rectangle::new(Point::zero(), Size::new(1, 1)),
+        255,
+    );
+    check(&mut compositor, &mut fb, |p| match p {
+        Point { x: 0, y: 0 } => 255,
+        p => frame[p.y as usize * W + p.x as usize],
+    });
+}
+
+#[test]
+fn dirty() {
+    let mut compositor = Compositor::new();
+    let mut fb = DmaFrameBuffer::new();
+    let area = Rectangle::new(Point::new(4, 2), Size::new(10, 6));
+    fill(&mut compositor, 0, area, 100);
+    check(&mut compositor, &mut fb, |p| {
+        if area.contains(p) {
+            100
+        } else {
+            0
+        }
+    });
+    assert_eq!(compositor.layer(0).dirty(), None);
+
+    // drawing or blitting what's already there doesn't change anything
+    fill(&mut compositor, 0, area, 100);
+    compositor.layer_mut(0).set_opacity(255);
+    assert_eq!(compositor.layer(0).dirty(), None);
+    let mut frame = vec![7; W * H];
+    compositor.layer_mut(1).blit_gray8(&frame);
+    check(&mut compositor, &mut fb, |_| 7);
+    compositor.layer_mut(1).blit_gray8(&frame);
+    assert_eq!(compositor.layer(1).dirty(), None);
+    // the area flattened last time gets drawn again, for the other buffer of a double buffer
+    check(&mut compositor, &mut fb, |_| 7);
+
+    // only the changed pixels are dirty
+    frame[2 * W + 30] = 8;
+    frame[5 * W + 20] = 9;
+    compositor.layer_mut(1).blit_gray8(&frame);
+    let changed = Rectangle::new(Point::new(20, 2), Size::new(11, 4));
+    assert_eq!(compositor.layer(1).dirty(), Some(changed));
+    compositor
+        .layer_mut(1)
+        .erase(&Rectangle::new(Point::new(1, 1), Size::new(1, 1)));
+    assert_eq!(
+        compositor.layer(1).dirty(),
+        Some(Rectangle::new(Point::new(1, 1), Size::new(30, 5)))
+    );
+
+    // and only the dirty area gets flattened, so something drawn straight into the framebuffer
+    // outside of it survives
+    fb.set_pixel(Point::new(60, 12), Gray8::WHITE);
+    check(&mut compositor, &mut fb, |p| match p {
+        Point { x: 60, y: 12 } => 255,
+        Point { x: 1, y: 1 } => 0,
+        p => frame[p.y as usize * W + p.x as usize],
+    });
+    compositor.invalidate();
+    check(&mut compositor, &mut fb, |p| match p {
+        Point { x: 1, y: 1 } => 0,
+        p => frame[p.y as usize * W + p.x as usize],
+    });
+}
+
+#[test]
+fn double_buffered() {
+    let mut compositor = Compositor::new();
+    // like a `DoubleBuffer`, each frame gets flattened into the buffer shown before the last one
+    let mut buffers: [DmaFrameBuffer; 2] = [DmaFrameBuffer::new(), DmaFrameBuffer::new()];
+    let areas = [
+        Rectangle::new(Point::new(4, 2), Size::new(10, 6)),
+        Rectangle::new(Point::new(30, 0), Size::new(5, 16)),
+        Rectangle::new(Point::new(50, 8), Size::new(2, 2)),
+    ];
+    for (frame, &area) in areas.iter().enumerate() {
+        fill(&mut compositor, 0, area, 10 * frame as u8 + 10);
+        check(&mut compositor, &mut buffers[frame % 2], |p| {
+            match areas[..=frame].iter().rposition(|a| a.contains(p)) {
+                Some(i) => 10 * i as u8 + 10,
+                None => 0,
+            }
+        });
+    }
+    // nothing changed since, but the older buffer is still missing the last frame
+    check(&mut compositor, &mut buffers[1], |p| {
+        match areas.iter().rposition(|a| a.contains(p)) {
+            Some(i) => 10 * i as u8 + 10,
+            None => 0,
+        }
+    });
+}
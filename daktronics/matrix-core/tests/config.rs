@@ -0,0 +1,66 @@
+use embedded_storage::{ReadStorage, Storage};
+use matrix_core::config::{ConfigError, ConfigStore, CONFIG_ENTRY_LEN, SSID_STORE_ID};
+use matrix_core::form::{decode_field, form_field};
+
+/// Flash-like storage in RAM, erased to all ones
+struct RamStorage(Vec<u8>);
+
+impl ReadStorage for RamStorage {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(self.0.get(offset..offset + bytes.len()).ok_or(())?);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Storage for RamStorage {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        let offset = offset as usize;
+        self.0
+            .get_mut(offset..offset + bytes.len())
+            .ok_or(())?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn config_round_trip() {
+    let mut store = ConfigStore::new(RamStorage(vec![0xff; 0x1000]), 0x100);
+    assert!(matches!(
+        store.get(SSID_STORE_ID),
+        Err(ConfigError::Invalid)
+    ));
+    store.set(SSID_STORE_ID, "my network").unwrap();
+    assert_eq!(store.get(SSID_STORE_ID).unwrap(), "my network");
+    // a shorter value replaces the whole entry
+    store.set(SSID_STORE_ID, "net").unwrap();
+    assert_eq!(store.get(SSID_STORE_ID).unwrap(), "net");
+    let long = "x".repeat(CONFIG_ENTRY_LEN + 1);
+    assert!(matches!(
+        store.set(SSID_STORE_ID, &long),
+        Err(ConfigError::TooLong)
+    ));
+    assert!(matches!(store.get(1000), Err(ConfigError::Storage(()))));
+}
+
+#[test]
+fn form_parsing() {
+    let request =
+        "POST / HTTP/1.1\r\nHost: 192.168.2.1\r\n\r\nssid=my%20net&pw=a%26b%3Dc&orientation=180";
+    assert_eq!(form_field(request, "ssid"), Some("my%20net"));
+    assert_eq!(form_field(request, "orientation"), Some("180"));
+    assert_eq!(form_field(request, "missing"), None);
+    assert_eq!(form_field("GET / HTTP/1.1\r\n", "ssid"), None);
+
+    assert_eq!(decode_field::<16>("my%20net").unwrap(), "my net");
+    assert_eq!(decode_field::<16>("a%26b%3Dc").unwrap(), "a&b=c");
+    assert_eq!(decode_field::<4>("too%20long"), None);
+    assert_eq!(decode_field::<16>("%ff"), None);
+}
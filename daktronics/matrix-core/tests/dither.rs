@@ -0,0 +1,123 @@
+//! Render dithered content through the emulator and check which levels light up
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use matrix_core::dither::{Dithering, SpatialDither};
+use matrix_core::encoding::DmaEncoding;
+use matrix_core::framebuffer::DmaFrameBuffer;
+use matrix_core::gamma::GammaLut;
+use matrix_emulator::{Emulator, Image};
+
+/// Between levels 1 and 2 with a linear curve, a bit under halfway
+const LUMA: u8 = 6;
+
+fn render(fb: &DmaFrameBuffer) -> Image {
+    let mut emulator = Emulator::af6700();
+    emulator.run(DmaEncoding::from(fb).transfers());
+    emulator.image()
+}
+
+fn framebuffer(dithering: &str) -> DmaFrameBuffer {
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_orientation("0".parse().unwrap());
+    fb.set_gamma(GammaLut::LINEAR);
+    fb.set_dithering(dithering.parse().unwrap());
+    fb
+}
+
+/// On-times of the two levels on either side of `LUMA`
+fn neighbours() -> (u32, u32) {
+    let mut fb = framebuffer("none");
+    fb.set_pixel(Point::new(0, 0), Gray8::new(4));
+    fb.set_pixel(Point::new(1, 0), Gray8::new(8));
+    let image = render(&fb);
+    (image.get(0, 0), image.get(1, 0))
+}
+
+/// How many of the pixels in `area` are at the higher of the two levels, checking that they're all
+/// at one or the other
+fn count_high(image: &Image, area: &Rectangle) -> usize {
+    let (low, high) = neighbours();
+    area.points()
+        .map(|p| image.get(p.x as usize, p.y as usize))
+        .inspect(|&t| assert!(t == low || t == high, "{t} isn't {low} or {high}"))
+        .filter(|&t| t == high)
+        .count()
+}
+
+#[test]
+fn round_trip() {
+    for s in ["none", "ordered", "diffusion+temporal", "ordered+temporal"] {
+        assert_eq!(s.parse::<Dithering>().unwrap().to_string(), s);
+    }
+    assert!("temporal".parse::<Dithering>().is_err());
+    assert_eq!(
+        "diffusion".parse::<Dithering>().unwrap(),
+        Dithering {
+            spatial: SpatialDither::Diffusion,
+            temporal: false
+        }
+    );
+}
+
+#[test]
+fn none_rounds() {
+    let mut fb = framebuffer("none");
+    let area = Rectangle::new(Point::zero(), Size::new(16, 16));
+    fb.fill_solid(&area, Gray8::new(LUMA)).unwrap();
+    assert_eq!(count_high(&render(&fb), &area), 0);
+}
+
+#[test]
+fn ordered() {
+    let mut fb = framebuffer("ordered");
+    let area = Rectangle::new(Point::new(8, 0), Size::new(16, 16));
+    fb.fill_solid(&area, Gray8::new(LUMA)).unwrap();
+    let image = render(&fb);
+    // LUMA is 0.48 of the way from level 1 to 2, and every 4×4 block gets 16 thresholds
+    assert_eq!(count_high(&image, &area), 128);
+
+    // the pattern doesn't depend on how the pixels were drawn
+    let mut slow = framebuffer("ordered");
+    for p in area.points() {
+        slow.set_pixel(p, Gray8::new(LUMA));
+    }
+    assert!(slow.as_bytes() == fb.as_bytes());
+}
+
+#[test]
+fn diffusion() {
+    let mut fb = framebuffer("diffusion");
+    let (w, h) = (DmaFrameBuffer::<2>::WIDTH, DmaFrameBuffer::<2>::HEIGHT);
+    fb.blit_gray8(&vec![LUMA; w * h]);
+    let high = count_high(&render(&fb), &fb.bounding_box());
+    let ideal = (w * h) as f32 * 0.48;
+    assert!((high as f32 - ideal).abs() < ideal * 0.05, "{high}");
+}
+
+#[test]
+fn temporal() {
+    let mut fb = framebuffer("none+temporal");
+    let area = Rectangle::new(Point::zero(), Size::new(8, 8));
+    fb.fill_solid(&area, Gray8::new(LUMA)).unwrap();
+    let (low, high) = neighbours();
+    let mut total = vec![0; 64];
+    for _ in 0..4 {
+        let image = render(&fb);
+        // half of the pixels are at the higher level at any one time
+        assert_eq!(count_high(&image, &area), 32);
+        for (t, p) in total.iter_mut().zip(area.points()) {
+            *t += image.get(p.x as usize, p.y as usize);
+        }
+        fb.advance_temporal_dither();
+    }
+    // and every pixel spends half of its time there
+    assert!(total.iter().all(|&t| t == 2 * low + 2 * high));
+
+    // without temporal dithering, nothing changes from one refresh cycle to the next
+    let mut fb = framebuffer("ordered");
+    fb.fill_solid(&area, Gray8::new(LUMA)).unwrap();
+    let before = render(&fb);
+    fb.advance_temporal_dither();
+    assert_eq!(render(&fb), before);
+}
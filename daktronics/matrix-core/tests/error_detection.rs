@@ -0,0 +1,60 @@
+use embedded_graphics::pixelcolor::{Gray8, GrayColor};
+use matrix_core::error_detection::{ErrorDetection, Fault, FaultMap, Test, SCAN_ROWS};
+use matrix_core::encoding::DmaEncoding;
+use matrix_core::framebuffer::{DmaFrameBuffer, Orientation};
+use matrix_emulator::Emulator;
+
+/// The status bits read back should be attributed to the LEDs that they belong to
+#[test]
+fn fault_positions() {
+    let chain_len = ErrorDetection::<2>::CHAIN_LEN;
+    for orientation in ["0", "180", "0h"] {
+        let mut fb: DmaFrameBuffer = DmaFrameBuffer::new();
+        fb.set_orientation(orientation.parse::<Orientation>().unwrap());
+        for row in 0..SCAN_ROWS {
+            for bit in (0..chain_len).step_by(29) {
+                let mut faults = FaultMap::new();
+                faults.record(&fb, row, Test::Open, bit, false);
+                let [(x, y, Fault::Open)] = faults.faults().collect::<Vec<_>>()[..] else {
+                    panic!("expected a single open LED");
+                };
+
+                // drawing the faulty pixel should light the LED that reported the fault
+                let mut pixel = fb;
+                pixel.set_pixel_internal(x, y, Gray8::WHITE);
+                let mut emulator = Emulator::af6700();
+                emulator.run(DmaEncoding::from(&pixel).transfers());
+                let image = emulator.image();
+                // the first bit read back is the one at the far end of the chain
+                let (led_x, led_y) = emulator.led(chain_len - 1 - bit, row);
+                assert!(
+                    image.get(led_x, led_y) > 0,
+                    "{orientation} row {row} bit {bit}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn report() {
+    let fb: DmaFrameBuffer = DmaFrameBuffer::new();
+    let mut faults = FaultMap::new();
+    assert!(faults.is_empty());
+    for bit in 0..ErrorDetection::<2>::CHAIN_LEN {
+        faults.record(&fb, 0, Test::Short, bit, true);
+    }
+    assert!(faults.is_empty());
+
+    faults.record(&fb, 3, Test::Open, 0, false);
+    faults.record(&fb, 5, Test::Short, 100, false);
+    let (open, short) = (0..2)
+        .map(|panel| faults.panel_counts(panel))
+        .fold((0, 0), |(o, s), (po, ps)| (o + po, s + ps));
+    assert_eq!((open, short), (1, 1));
+
+    let mut report = String::new();
+    faults.write_report(&mut report).unwrap();
+    assert_eq!(report.lines().count(), 4);
+    assert!(report.starts_with("panel 0: "));
+}
@@ -0,0 +1,293 @@
+//! Render through the emulator and check what actually lights up
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use matrix_core::encoding::DmaEncoding;
+use matrix_core::framebuffer::{DmaFrameBuffer, Orientation, BITS, ROWS};
+use matrix_core::gamma::GammaLut;
+use matrix_emulator::{Emulator, Image};
+
+fn render(fb: &DmaFrameBuffer) -> Image {
+    let mut emulator = Emulator::af6700();
+    emulator.run(DmaEncoding::from(fb).transfers());
+    emulator.image()
+}
+
+/// Positions of all of the LEDs that were lit
+fn lit(image: &Image) -> Vec<(usize, usize)> {
+    (0..image.height)
+        .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+        .filter(|&(x, y)| image.get(x, y) > 0)
+        .collect()
+}
+
+#[test]
+fn blank() {
+    let fb = DmaFrameBuffer::new();
+    assert!(lit(&render(&fb)).is_empty());
+}
+
+#[test]
+fn orientation() {
+    let (w, h) = (DmaFrameBuffer::<2>::WIDTH, DmaFrameBuffer::<2>::HEIGHT);
+    for (orientation, expected) in [
+        ("0", (3, 5)),
+        ("0h", (w - 1 - 3, 5)),
+        ("0v", (3, h - 1 - 5)),
+        ("180", (w - 1 - 3, h - 1 - 5)),
+        ("180hv", (3, 5)),
+    ] {
+        let mut fb = DmaFrameBuffer::new();
+        fb.set_orientation(orientation.parse::<Orientation>().unwrap());
+        fb.set_pixel(Point::new(3, 5), Gray8::WHITE);
+        assert_eq!(lit(&render(&fb)), [expected], "orientation {orientation}");
+    }
+}
+
+#[test]
+fn every_pixel() {
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_orientation("0".parse().unwrap());
+    DrawTarget::clear(&mut fb, Gray8::WHITE).unwrap();
+    let image = render(&fb);
+    let on_time = image.get(0, 0);
+    assert!(on_time > 0);
+    assert!(image.on_time.iter().all(|&t| t == on_time));
+}
+
+#[test]
+fn gray_levels() {
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_orientation("0".parse().unwrap());
+    fb.set_gamma(GammaLut::LINEAR);
+    for x in 0..64 {
+        fb.set_pixel(Point::new(x, 0), Gray8::new((x * 4 + x / 16) as u8));
+    }
+    let image = render(&fb);
+    let full = render(&{
+        let mut fb = DmaFrameBuffer::new();
+        fb.set_orientation("0".parse().unwrap());
+        fb.set_pixel(Point::new(0, 0), Gray8::WHITE);
+        fb
+    })
+    .get(0, 0);
+    for x in 1..64 {
+        assert!(image.get(x, 0) > image.get(x - 1, 0), "level {x}");
+        // within a couple of percent of the ideal
+        let ideal = full as f32 * x as f32 / 63.0;
+        assert!(
+            (image.get(x, 0) as f32 - ideal).abs() <= full as f32 * 0.02,
+            "level {x}"
+        );
+    }
+}
+
+#[test]
+fn brightness() {
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_pixel(Point::new(0, 0), Gray8::WHITE);
+    let full = render(&fb);
+    fb.set_brightness(u8::MAX / 2);
+    let half = render(&fb);
+    assert_eq!(lit(&full), lit(&half));
+    let (full, half) = (
+        full.on_time.iter().sum::<u32>(),
+        half.on_time.iter().sum::<u32>(),
+    );
+    assert!(half.abs_diff(full / 2) <= full / 50);
+}
+
+#[test]
+fn bulk_drawing() {
+    use embedded_graphics::primitives::Rectangle;
+
+    let (w, h) = (DmaFrameBuffer::<2>::WIDTH, DmaFrameBuffer::<2>::HEIGHT);
+    let image: Vec<u8> = (0..w * h).map(|i| (i * 7) as u8).collect();
+    let area = Rectangle::new(Point::new(-3, 4), Size::new(20, 15));
+    let patch: Vec<u8> = (0..20 * 15).map(|i| (i * 13) as u8).collect();
+    for orientation in ["0", "0h", "0v", "180", "180h"] {
+        let orientation = orientation.parse::<Orientation>().unwrap();
+        let mut slow: DmaFrameBuffer = DmaFrameBuffer::new();
+        let mut fast: DmaFrameBuffer = DmaFrameBuffer::new();
+        slow.set_orientation(orientation);
+        fast.set_orientation(orientation);
+
+        for (i, &luma) in image.iter().enumerate() {
+            slow.set_pixel_internal(i % w, i / w, Gray8::new(luma));
+        }
+        fast.blit_gray8(&image);
+        assert!(
+            slow.as_bytes() == fast.as_bytes(),
+            "blit_gray8 {orientation}"
+        );
+
+        for (p, &luma) in area.points().zip(&patch) {
+            slow.set_pixel(p, Gray8::new(luma));
+        }
+        fast.blit_gray8_area(&area, &patch);
+        assert!(
+            slow.as_bytes() == fast.as_bytes(),
+            "blit_gray8_area {orientation}"
+        );
+
+        let inside = Rectangle::new(Point::new(40, 2), Size::new(50, 9));
+        for (p, &luma) in inside.points().zip(&patch) {
+            slow.set_pixel(p, Gray8::new(luma));
+        }
+        fast.fill_contiguous(&inside, patch.iter().map(|&l| Gray8::new(l)))
+            .unwrap();
+        assert!(
+            slow.as_bytes() == fast.as_bytes(),
+            "fill_contiguous {orientation}"
+        );
+
+        for p in area.points() {
+            slow.set_pixel(p, Gray8::new(100));
+        }
+        fast.fill_solid(&area, Gray8::new(100)).unwrap();
+        assert!(
+            slow.as_bytes() == fast.as_bytes(),
+            "fill_solid {orientation}"
+        );
+
+        for i in 0..w * h {
+            slow.set_pixel_internal(i % w, i / w, Gray8::new(30));
+        }
+        DrawTarget::clear(&mut fast, Gray8::new(30)).unwrap();
+        assert!(slow.as_bytes() == fast.as_bytes(), "clear {orientation}");
+
+        // empty areas, whether they're inside or off the display, draw nothing
+        fast.blit_gray8_area(&Rectangle::new(Point::new(5, 5), Size::new(0, 3)), &[]);
+        fast.blit_gray8_area(&Rectangle::new(Point::new(-9, 5), Size::new(4, 2)), &[0; 8]);
+        assert!(slow.as_bytes() == fast.as_bytes(), "empty {orientation}");
+    }
+}
+
+#[test]
+fn timing() {
+    use matrix_core::timing::DisplayTiming;
+
+    let default = DisplayTiming::default();
+    assert_eq!(default.to_string().parse(), Ok(default));
+    assert!("1000,25,30,100".parse::<DisplayTiming>().is_err());
+    assert!("1000,25,1".parse::<DisplayTiming>().is_err());
+
+    // parses, but the blanking is longer than a row
+    let too_long: DisplayTiming = "1000,1000,500,100".parse().unwrap();
+    assert!(<DmaFrameBuffer>::supports_timing(&default));
+    assert!(!<DmaFrameBuffer>::supports_timing(&too_long));
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_timing(too_long);
+    fb.set_pixel(Point::new(0, 0), Gray8::WHITE);
+    assert!(lit(&render(&fb)).is_empty());
+
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_pixel(Point::new(0, 0), Gray8::WHITE);
+    let full = render(&fb);
+    fb.set_timing("1000,25,4,50".parse().unwrap());
+    let half = render(&fb);
+    assert_eq!(lit(&full), lit(&half));
+    let (full, half) = (
+        full.on_time.iter().sum::<u32>(),
+        half.on_time.iter().sum::<u32>(),
+    );
+    assert!(half.abs_diff(full / 2) <= full / 50);
+
+    // more blanking only takes time away from the on-time
+    fb.set_timing("1000,100,1,100".parse().unwrap());
+    let blanked = render(&fb).on_time.iter().sum::<u32>();
+    assert!(blanked < full && blanked > half);
+}
+
+/// The sequence the SPI driver sends for one refresh, as it looks on the pins: shift a row in with
+/// the display blanked, latch it, then light it for the plane's on-time. Every byte here is a clock
+/// cycle, so LE/MOD goes high with the last bit, since the driver pulses it without clocking.
+fn spi_refresh(fb: &DmaFrameBuffer) -> Vec<u8> {
+    const VALUE: u8 = 1 << 5;
+    const LE_MOD: u8 = 1 << 4;
+    const BLANK: u8 = 1 << 3;
+    let mut pins = vec![];
+    for plane in 0..BITS as usize {
+        for row in 0..ROWS {
+            let row_pins = row as u8;
+            pins.extend(
+                fb.row_bits(plane, row)
+                    .map(|bit| if bit { VALUE } else { 0 } | BLANK | row_pins),
+            );
+            *pins.last_mut().unwrap() |= LE_MOD;
+            pins.extend(std::iter::repeat_n(row_pins, fb.plane_lit_cycles(plane)));
+            pins.push(BLANK | row_pins);
+        }
+    }
+    pins
+}
+
+#[test]
+fn row_bits() {
+    let mut fb = DmaFrameBuffer::new();
+    for (i, luma) in [255, 128, 40, 3].into_iter().enumerate() {
+        fb.set_pixel(Point::new(i as i32 * 25, i as i32 * 5), Gray8::new(luma));
+    }
+    fb.set_brightness(100);
+    let mut emulator = Emulator::af6700();
+    emulator.transfer(&spi_refresh(&fb));
+    assert_eq!(emulator.image(), render(&fb));
+}
+
+#[test]
+fn encoding_updates() {
+    let mut fb = DmaFrameBuffer::new();
+    fb.set_pixel(Point::new(7, 2), Gray8::new(200));
+    let mut encoding = DmaEncoding::from(&fb);
+
+    // one encoding can follow different framebuffers, rewriting only what changed
+    let mut other: DmaFrameBuffer = DmaFrameBuffer::new();
+    other.set_pixel(Point::new(50, 9), Gray8::new(90));
+    other.set_brightness(70);
+    for fb in [other, fb] {
+        encoding.update(&fb);
+        assert!(encoding.as_bytes() == DmaEncoding::from(&fb).as_bytes());
+    }
+    fb.set_timing("1000,40,3,80".parse().unwrap());
+    DrawTarget::clear(&mut fb, Gray8::new(160)).unwrap();
+    encoding.update(&fb);
+    assert!(encoding.as_bytes() == DmaEncoding::from(&fb).as_bytes());
+}
+
+#[test]
+fn back_to_back() {
+    let mut fb = DmaFrameBuffer::new();
+    for (i, luma) in [255, 128, 40, 3].into_iter().enumerate() {
+        fb.set_pixel(Point::new(i as i32 * 25, i as i32 * 5 + 3), Gray8::new(luma));
+    }
+    let once = render(&fb);
+
+    // refreshes carry on from each other without anything of one showing up in the next
+    let encoding = DmaEncoding::from(&fb);
+    let mut emulator = Emulator::af6700();
+    emulator.run(encoding.transfers().chain(encoding.transfers()));
+    let twice = emulator.image();
+    assert!(twice.on_time.iter().zip(&once.on_time).all(|(&t, &o)| t == o * 2));
+    assert_eq!(
+        encoding.transfers().map(<[u8]>::len).sum::<usize>(),
+        DmaFrameBuffer::<2>::refresh_len()
+    );
+
+    // and a dump of the encoding renders the same
+    let mut emulator = Emulator::af6700();
+    emulator.refresh(encoding.as_bytes(), BITS as usize);
+    assert_eq!(emulator.image(), once);
+}
+
+#[test]
+fn custom_gamma() {
+    assert_eq!("linear".parse(), Ok(GammaLut::LINEAR));
+    let curve: GammaLut = "0,250,1000".parse().unwrap();
+    assert_eq!(curve.intensity(0), 0);
+    assert_eq!(curve.intensity(255), u16::MAX);
+    // halfway along the curve is its middle point, and a quarter is halfway along the first half
+    assert!(curve.intensity(128).abs_diff(u16::MAX / 4) < 300);
+    assert!(curve.intensity(64).abs_diff(u16::MAX / 8) < 300);
+    for bad in ["", "1000", "0,1001", "0,x,1000"] {
+        assert!(bad.parse::<GammaLut>().is_err(), "{bad}");
+    }
+}
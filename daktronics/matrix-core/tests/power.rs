@@ -0,0 +1,146 @@
+//! Current estimates and the brightness limits that come from them
+use embedded_graphics::pixelcolor::Gray8;
+use embedded_graphics::prelude::*;
+use matrix_core::framebuffer::DmaFrameBuffer;
+use matrix_core::power::{PowerBudget, PowerConfig, PowerStats};
+
+fn white() -> DmaFrameBuffer {
+    let mut fb = DmaFrameBuffer::new();
+    DrawTarget::clear(&mut fb, Gray8::WHITE).unwrap();
+    fb
+}
+
+#[test]
+fn config_round_trip() {
+    let config = PowerConfig::default();
+    assert_eq!(config.to_string().parse(), Ok(config));
+    assert_eq!(
+        "20000,100,5000,1000,60,80,0".parse(),
+        Ok(PowerConfig {
+            led_current_ua: 20_000,
+            base_current_ma: 100,
+            supply_mv: 5000,
+            max_current_ma: 1000,
+            throttle_temp: 60,
+            max_temp: 80,
+            min_brightness: 0,
+        })
+    );
+    for bad in [
+        "",
+        "1,2,3,4,5,6",
+        "1,2,3,4,5,6,7,8",
+        "1,2,3,4,80,80,7",
+        "1,2,3,4,5,6,256",
+        "1,2,3,4,-2147483648,2147483647,7",
+        "1,2,3,4,70,1000,7",
+    ] {
+        assert_eq!(bad.parse::<PowerConfig>(), Err(()), "{bad}");
+    }
+}
+
+#[test]
+fn estimates() {
+    let config = PowerConfig {
+        max_current_ma: 100_000,
+        ..PowerConfig::default()
+    };
+    let mut budget = PowerBudget::new(config);
+    let black: DmaFrameBuffer = DmaFrameBuffer::new();
+    budget.set_frame(&black);
+    assert_eq!(budget.current_ma(255), config.base_current_ma);
+    assert_eq!(budget.power_mw(255), config.base_current_ma * 5);
+
+    let white = white();
+    budget.set_frame(&white);
+    let full = budget.current_ma(255) - config.base_current_ma;
+    // only one scan row is lit at once, which is two pixels per column, and not all the time
+    let columns = DmaFrameBuffer::<2>::WIDTH as u32 * 2;
+    assert!(full > 0 && full < columns * config.led_current_ua / 1000);
+    let half = budget.current_ma(128) - config.base_current_ma;
+    assert!(half.abs_diff(full * 128 / 255) <= 1);
+
+    // a dimmer frame draws less
+    let mut grey: DmaFrameBuffer = DmaFrameBuffer::new();
+    DrawTarget::clear(&mut grey, Gray8::new(128)).unwrap();
+    budget.set_frame(&grey);
+    assert!(budget.current_ma(255) - config.base_current_ma < full);
+}
+
+#[test]
+fn current_limit() {
+    let mut budget = PowerBudget::new(PowerConfig {
+        max_current_ma: 100_000,
+        ..PowerConfig::default()
+    });
+    budget.set_frame(&white());
+    let full = budget.current_ma(255);
+
+    // half of the LED current fits
+    let config = PowerConfig {
+        max_current_ma: (full + 150) / 2,
+        ..PowerConfig::default()
+    };
+    let mut budget = PowerBudget::new(config);
+    budget.set_frame(&white());
+    let limited = budget.limit(255, None);
+    assert!(limited.abs_diff(127) <= 1, "{limited}");
+    assert!(budget.current_ma(limited) <= config.max_current_ma);
+    // asking for less than the limit is fine
+    assert_eq!(budget.limit(50, None), 50);
+
+    // a black frame doesn't need limiting, but the brightness comes back slowly
+    budget.set_frame(&DmaFrameBuffer::<2>::new());
+    assert_eq!(budget.limit(255, None), 51);
+    assert_eq!(budget.limit(255, None), 52);
+    for _ in 0..255 {
+        budget.limit(255, None);
+    }
+    assert_eq!(budget.limit(255, None), 255);
+}
+
+#[test]
+fn thermal_limit() {
+    let mut budget = PowerBudget::new(PowerConfig {
+        throttle_temp: 70,
+        max_temp: 80,
+        min_brightness: 55,
+        ..PowerConfig::default()
+    });
+    assert_eq!(budget.limit(200, Some(70)), 200);
+    assert_eq!(budget.limit(255, Some(90)), 55);
+    assert_eq!(budget.limit(255, Some(75)), 56);
+    let mut budget = PowerBudget::new(PowerConfig {
+        throttle_temp: 70,
+        max_temp: 80,
+        min_brightness: 55,
+        ..PowerConfig::default()
+    });
+    assert_eq!(budget.limit(255, Some(75)), 155);
+    assert_eq!(budget.limit(255, Some(-10)), 156);
+
+    // limits that weren't parsed aren't checked, but don't overflow either
+    let mut budget = PowerBudget::new(PowerConfig {
+        throttle_temp: i32::MIN,
+        max_temp: i32::MAX,
+        min_brightness: 0,
+        ..PowerConfig::default()
+    });
+    assert_eq!(budget.limit(255, Some(i32::MAX)), 0);
+}
+
+#[test]
+fn stats() {
+    let stats = PowerStats::new();
+    assert_eq!(stats.temperature(), None);
+    stats.record_temperature(-3);
+    assert_eq!(stats.temperature(), Some(-3));
+
+    let mut budget = PowerBudget::new(PowerConfig::default());
+    budget.set_frame(&white());
+    stats.record_estimate(&budget, 100);
+    assert_eq!(stats.current_ma(), budget.current_ma(100));
+    assert_eq!(stats.power_mw(), budget.power_mw(100));
+    assert_eq!(stats.brightness(), 100);
+    assert!(stats.to_string().contains("chip temperature: -3 °C"));
+}
@@ -0,0 +1,83 @@
+//! Test patterns, rendered through the emulator to check which LEDs they light
+use matrix_core::encoding::DmaEncoding;
+use matrix_core::framebuffer::{DmaFrameBuffer, ROWS};
+use matrix_core::test_pattern::TestPattern;
+use matrix_emulator::{Emulator, Image};
+
+/// Draw step `step` of `pattern` straight into a framebuffer in orientation `orientation`
+fn render(pattern: TestPattern, orientation: &str, step: usize) -> Image {
+    let mut fb = DmaFrameBuffer::<2>::new();
+    fb.set_orientation(orientation.parse().unwrap());
+    let mapping = fb;
+    pattern.draw(&mut fb, &mapping, step).unwrap();
+    let mut emulator = Emulator::af6700();
+    emulator.run(DmaEncoding::from(&fb).transfers());
+    emulator.image()
+}
+
+fn lit(image: &Image) -> Vec<(usize, usize)> {
+    (0..image.height)
+        .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+        .filter(|&(x, y)| image.get(x, y) > 0)
+        .collect()
+}
+
+#[test]
+fn names() {
+    for pattern in TestPattern::ALL {
+        assert_eq!(pattern.to_string().parse(), Ok(pattern));
+    }
+    assert!("off".parse::<TestPattern>().is_err());
+    let mut pattern = TestPattern::ALL[0];
+    let mut count = 1;
+    while let Some(next) = pattern.next() {
+        pattern = next;
+        count += 1;
+    }
+    assert_eq!(count, TestPattern::ALL.len());
+}
+
+#[test]
+fn walks_follow_the_hardware() {
+    let width = DmaFrameBuffer::<2>::WIDTH;
+    // the same LEDs light up whichever way round the sign is mounted
+    for orientation in ["0", "180", "0h"] {
+        let row = 3;
+        let expected: Vec<_> = [row, row + ROWS]
+            .into_iter()
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .collect();
+        let image = render(TestPattern::RowWalk, orientation, row + ROWS);
+        assert_eq!(lit(&image), expected, "row walk {orientation}");
+
+        // the chain runs across the top half of the assembly, then back across the bottom half
+        let image = render(TestPattern::ColumnWalk, orientation, width + 5);
+        let expected: Vec<_> = (ROWS..2 * ROWS).map(|y| (5, y)).collect();
+        assert_eq!(lit(&image), expected, "column walk {orientation}");
+    }
+}
+
+#[test]
+fn static_patterns() {
+    let image = render(TestPattern::AllOn, "180", 0);
+    assert_eq!(lit(&image).len(), image.width * image.height);
+
+    let even = lit(&render(TestPattern::Checkerboard, "0", 0));
+    let odd = lit(&render(TestPattern::Checkerboard, "0", 1));
+    assert_eq!(even.len() + odd.len(), 96 * 16);
+    assert!(even.iter().all(|p| !odd.contains(p)));
+
+    let ramp = render(TestPattern::GrayRamp, "0", 0);
+    assert_eq!(ramp.get(0, 0), 0);
+    assert!(ramp.get(95, 7) > ramp.get(48, 7) && ramp.get(48, 7) > ramp.get(10, 7));
+
+    // every panel is outlined, whichever way round it is
+    for orientation in ["0", "180"] {
+        let image = render(TestPattern::PanelIds, orientation, 0);
+        for (x, y) in [(0, 0), (47, 15), (48, 0), (95, 15)] {
+            assert!(image.get(x, y) > 0, "{orientation} ({x}, {y})");
+        }
+    }
+    assert_eq!(TestPattern::GrayRamp.step_ms(), None);
+    assert!(TestPattern::RefreshTiming.step_ms().is_some());
+}
@@ -0,0 +1,40 @@
+use matrix_core::watchdog::{Subsystem, Supervisor};
+
+#[test]
+fn stalls() {
+    let supervisor = Supervisor::new();
+    // nothing is watched until it checks in
+    assert_eq!(supervisor.stalled(1_000_000), None);
+
+    supervisor.check_in(Subsystem::Render, 100);
+    supervisor.check_in(Subsystem::Arrivals, 100);
+    supervisor.check_in(Subsystem::Video, 100);
+    assert_eq!(supervisor.stalled(1100), None);
+    assert_eq!(supervisor.stalled(1101), Some(Subsystem::Render));
+
+    supervisor.check_in(Subsystem::Render, 1101);
+    assert_eq!(supervisor.stalled(2000), None);
+    supervisor.check_in(Subsystem::Render, 5000);
+    // one content source hanging is caught while the others keep going
+    supervisor.check_in(Subsystem::Arrivals, 5000);
+    assert_eq!(supervisor.stalled(5200), Some(Subsystem::Video));
+
+    supervisor.pause(Subsystem::Video);
+    assert_eq!(supervisor.stalled(5200), None);
+}
+
+#[test]
+fn clock_wraps() {
+    let supervisor = Supervisor::new();
+    supervisor.check_in(Subsystem::Render, u32::MAX - 100);
+    assert_eq!(supervisor.stalled(500), None);
+    assert_eq!(supervisor.stalled(1000), Some(Subsystem::Render));
+}
+
+#[test]
+fn retained_round_trip() {
+    for subsystem in Subsystem::ALL {
+        assert_eq!(Subsystem::from_u8(subsystem as u8), Some(subsystem));
+    }
+    assert_eq!(Subsystem::from_u8(0xff), None);
+}
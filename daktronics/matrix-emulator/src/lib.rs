@@ -0,0 +1,172 @@
+/// Host-side emulator for the AF-6700 LED matrix assembly.
+///
+/// This takes the byte stream that the PARL_IO driver sends to the matrix (one byte per clock
+/// cycle, see `Entry` in matrix-core's `encoding.rs`) and models what the hardware does with it:
+/// - the MBI5169 column drivers, which form one long shift register clocked by CD CLK, with a
+///   transparent output latch controlled by CD LE/MOD
+/// - the HEF4028 row decoder, which lights row 0-7 for an input of 0-7 on RD A0-A3, and nothing at
+///   all for 8 and up (this is how the driver blanks the display)
+///
+/// The result is the number of clock cycles that each LED was lit for, which is proportional to
+/// its brightness. See led-matrices.md for the details of the hardware.
+use std::io;
+use std::io::Write;
+
+/// Width of a single LED matrix panel
+pub const PANEL_WIDTH: usize = 48;
+/// Height of a single LED matrix panel
+pub const PANEL_HEIGHT: usize = 16;
+/// Number of rows selected by the row decoder
+pub const ROWS: usize = PANEL_HEIGHT / 2;
+
+/// Pin assignments within each byte of the stream
+const ROW_MASK: u8 = 0b1111;
+const LE_MOD: u8 = 1 << 4;
+const SDI: u8 = 1 << 5;
+
+/// Per-pixel on-time of the display, in clock cycles, from the front of the panels (as if they
+/// were mounted upright).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub on_time: Vec<u32>,
+}
+
+impl Image {
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.on_time[y * self.width + x]
+    }
+
+    /// The image as the AF-6700 shows it, with the panels mounted upside down
+    pub fn rotated_180(&self) -> Self {
+        let mut on_time = self.on_time.clone();
+        on_time.reverse();
+        Self { on_time, ..*self }
+    }
+
+    /// Scale the on-times to 0-255, relative to the longest possible on-time
+    pub fn to_gray8(&self, max_on_time: u32) -> Vec<u8> {
+        self.on_time
+            .iter()
+            .map(|&t| (t.min(max_on_time) as u64 * 255 / max_on_time.max(1) as u64) as u8)
+            .collect()
+    }
+
+    /// Write the image as a binary PGM, scaled so that `max_on_time` is white
+    pub fn write_pgm(&self, mut w: impl Write, max_on_time: u32) -> io::Result<()> {
+        write!(w, "P5\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.to_gray8(max_on_time))
+    }
+}
+
+/// State of a chain of LED matrix panels
+pub struct Emulator {
+    panels_per_assembly: usize,
+    width: usize,
+    /// Shift register contents, index 0 being the end closest to the data input
+    shift: Vec<bool>,
+    latch: Vec<bool>,
+    on_time: Vec<u32>,
+}
+
+impl Emulator {
+    /// Emulate `panels` panels side by side, grouped into assemblies of `panels_per_assembly`
+    /// panels that each run one serpentine: the chain goes across the top quadrants of an assembly,
+    /// then back across the bottom quadrants, before moving on to the next assembly.
+    pub fn new(panels: usize, panels_per_assembly: usize) -> Self {
+        assert!(panels > 0 && panels.is_multiple_of(panels_per_assembly));
+        let width = panels * PANEL_WIDTH;
+        let chain = width * 2;
+        Self {
+            panels_per_assembly,
+            width,
+            shift: vec![false; chain],
+            latch: vec![false; chain],
+            on_time: vec![0; width * PANEL_HEIGHT],
+        }
+    }
+
+    /// A single AF-6700 assembly
+    pub fn af6700() -> Self {
+        Self::new(2, 2)
+    }
+
+    /// Physical position of the LED driven by output `n` of the chain (counted from the data
+    /// input), for a given row
+    pub fn led(&self, n: usize, row: usize) -> (usize, usize) {
+        // the first bit clocked into a row ends up at the far end of the chain
+        let t = self.shift.len() - 1 - n;
+        let assembly_width = self.panels_per_assembly * PANEL_WIDTH;
+        let (assembly, t) = (t / (assembly_width * 2), t % (assembly_width * 2));
+        let (half, x) = (t / assembly_width, t % assembly_width);
+        (assembly * assembly_width + x, half * ROWS + row)
+    }
+
+    /// Clock a single DMA transfer through the matrix
+    pub fn transfer(&mut self, data: &[u8]) {
+        for &entry in data {
+            // data is shifted in on the clock edge, and the latch is transparent while LE is high
+            self.shift.rotate_right(1);
+            self.shift[0] = entry & SDI != 0;
+            if entry & LE_MOD != 0 {
+                self.latch.copy_from_slice(&self.shift);
+            }
+
+            let row = (entry & ROW_MASK) as usize;
+            if row < ROWS {
+                for n in 0..self.latch.len() {
+                    if self.latch[n] {
+                        let (x, y) = self.led(n, row);
+                        self.on_time[y * self.width + x] += 1;
+                    }
+                }
+            }
+        }
+        // in between transfers the PARL_IO peripheral outputs its idle value, which blanks the
+        // display and doesn't clock anything in
+    }
+
+    /// Clock a sequence of DMA transfers through the matrix, e.g. `DmaEncoding::transfers`
+    pub fn run<'a>(&mut self, transfers: impl IntoIterator<Item = &'a [u8]>) {
+        for data in transfers {
+            self.transfer(data);
+        }
+    }
+
+    /// Clock one full refresh of a `DmaEncoding` through the matrix, the same way the driver
+    /// sends it: `buffer` is the data from `DmaEncoding::as_bytes`, made up of `bits` equally
+    /// sized bit planes of `ROWS` rows and a tail of one more row. The planes above `bits - 2` are
+    /// repeated to make up their weight, and the tail goes last.
+    pub fn refresh(&mut self, buffer: &[u8], bits: usize) {
+        let rows = bits * ROWS + 1;
+        assert_eq!(buffer.len() % rows, 0);
+        let (planes, tail) = buffer.split_at(buffer.len() / rows * ROWS * bits);
+        let full_plane = bits - 2;
+        for (plane, data) in planes.chunks(planes.len() / bits).enumerate() {
+            let repeats = if plane > full_plane {
+                1 << (plane - full_plane)
+            } else {
+                1
+            };
+            for _ in 0..repeats {
+                self.transfer(data);
+            }
+        }
+        self.transfer(tail);
+    }
+
+    /// Reset the accumulated on-times
+    pub fn reset(&mut self) {
+        self.on_time.fill(0);
+    }
+
+    /// The on-times accumulated since the last reset
+    pub fn image(&self) -> Image {
+        Image {
+            width: self.width,
+            height: PANEL_HEIGHT,
+            on_time: self.on_time.clone(),
+        }
+    }
+}
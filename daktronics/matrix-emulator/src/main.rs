@@ -0,0 +1,36 @@
+use matrix_emulator::Emulator;
+use std::io::BufWriter;
+use std::process::exit;
+
+/// Render a dump of a `DmaEncoding` (the bytes from `DmaEncoding::as_bytes`) to a PGM image.
+///
+/// Usage: `matrix-emulator <dump> [bits] [panels] > out.pgm`
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <dump> [bits] [panels] > out.pgm", args[0]);
+        exit(1);
+    }
+    let buffer = std::fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", args[1], e);
+        exit(1);
+    });
+    let bits = args
+        .get(2)
+        .map_or(6, |b| b.parse().expect("invalid bit count"));
+    let panels = args
+        .get(3)
+        .map_or(2, |p| p.parse().expect("invalid panel count"));
+
+    let mut emulator = Emulator::new(panels, 2);
+    emulator.refresh(&buffer, bits);
+    let image = emulator.image().rotated_180();
+    let max = image.on_time.iter().copied().max().unwrap_or(0);
+    eprintln!(
+        "{}x{}, longest on-time {} clocks",
+        image.width, image.height, max
+    );
+    image
+        .write_pgm(BufWriter::new(std::io::stdout().lock()), max)
+        .expect("failed to write image");
+}